@@ -1,29 +1,115 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use crate::{
+    circuit_breaker::Breaker,
+    error::ErrorRenderer,
+    inner::{InnerStrangler, InnerStranglerService, Timeouts},
+    upstream::Pool,
+    CircuitBreaker, ForwardedHeaders, HttpScheme, InvalidBaseUri, LoadBalancing, OutlierEjection,
+    RetryPolicy, RewriteRule, Shadow, Strangler, StranglerError, StranglerInterceptor,
+};
 #[cfg(feature = "websocket")]
-use crate::WebSocketScheme;
 use crate::{
-    inner::{InnerStrangler, InnerStranglerService},
-    HttpScheme, Strangler,
+    inner::{WebSocketClient, WebSocketSettings},
+    WebSocketScheme,
 };
 
 pub struct StranglerBuilder {
-    authority: axum::http::uri::Authority,
+    authorities: Vec<axum::http::uri::Authority>,
+    load_balancing: LoadBalancing,
+    outlier_ejection: Option<OutlierEjection>,
     http_scheme: HttpScheme,
     #[cfg(feature = "websocket")]
     web_socket_scheme: WebSocketScheme,
     rewrite_strangled_request_host_header: bool,
+    error_renderer: ErrorRenderer,
+    timeouts: Timeouts,
+    retry_policy: Option<RetryPolicy>,
+    circuit_breaker: Option<CircuitBreaker>,
+    rewrite_rules: Vec<RewriteRule>,
+    base_path: Option<String>,
+    forwarded_headers: Option<ForwardedHeaders>,
+    interceptors: Vec<Arc<dyn StranglerInterceptor>>,
+    shadow: Option<Shadow>,
+    #[cfg(feature = "websocket")]
+    web_socket_settings: WebSocketSettings,
+    #[cfg(feature = "health-check")]
+    health_check: Option<crate::HealthCheck>,
 }
 
 impl StranglerBuilder {
     pub fn new(authority: axum::http::uri::Authority) -> Self {
+        Self::new_pool([authority])
+    }
+
+    /// Spreads the requests over several instances of the strangled service, see
+    /// [`StranglerBuilder::with_load_balancing`].
+    ///
+    /// # Panics
+    /// When building the `Strangler` if there are no `authorities`.
+    pub fn new_pool(authorities: impl IntoIterator<Item = axum::http::uri::Authority>) -> Self {
         Self {
-            authority,
+            authorities: authorities.into_iter().collect(),
+            load_balancing: LoadBalancing::RoundRobin,
+            outlier_ejection: Some(OutlierEjection::default()),
             http_scheme: HttpScheme::HTTP,
             #[cfg(feature = "websocket")]
             web_socket_scheme: WebSocketScheme::WS,
             rewrite_strangled_request_host_header: false,
+            error_renderer: Arc::new(axum::response::IntoResponse::into_response),
+            timeouts: Timeouts::default(),
+            retry_policy: None,
+            circuit_breaker: None,
+            rewrite_rules: Vec::new(),
+            base_path: None,
+            forwarded_headers: None,
+            interceptors: Vec::new(),
+            shadow: None,
+            #[cfg(feature = "websocket")]
+            web_socket_settings: WebSocketSettings::default(),
+            #[cfg(feature = "health-check")]
+            health_check: None,
+        }
+    }
+
+    /// Takes the scheme, authority and path of the strangled service from a base uri like
+    /// `https://legacy.internal/app/`.
+    /// The websocket scheme matches the http scheme, and the path of every request is joined with
+    /// the base path, after applying the rewrite rules: `/users` is forwarded as `/app/users`.
+    ///
+    /// Fails if the scheme needs a feature of this crate that isn't enabled, e.g. `https` without
+    /// the `https` feature.
+    pub fn from_base_uri(base_uri: axum::http::Uri) -> Result<Self, InvalidBaseUri> {
+        let authority = base_uri
+            .authority()
+            .cloned()
+            .ok_or(InvalidBaseUri::MissingAuthority)?;
+        if base_uri.query().is_some() {
+            return Err(InvalidBaseUri::UnexpectedQuery);
         }
+
+        let builder = match base_uri.scheme_str() {
+            Some("http") => {
+                let builder = Self::new(authority).with_http_scheme(HttpScheme::HTTP);
+                #[cfg(feature = "websocket")]
+                let builder = builder.with_web_socket_scheme(WebSocketScheme::WS);
+                builder
+            }
+            Some("https") => {
+                let builder = Self::new(authority).with_http_scheme(https_scheme()?);
+                #[cfg(feature = "websocket")]
+                let builder = builder.with_web_socket_scheme(wss_scheme()?);
+                builder
+            }
+            Some(scheme) => return Err(InvalidBaseUri::UnsupportedScheme(scheme.to_owned())),
+            None => return Err(InvalidBaseUri::MissingScheme),
+        };
+
+        let base_path = base_uri.path().trim_end_matches('/');
+        Ok(Self {
+            base_path: (!base_path.is_empty()).then(|| base_path.to_owned()),
+            ..builder
+        })
     }
 
     /// The default is `HttpScheme::HTTP`
@@ -56,35 +142,349 @@ impl StranglerBuilder {
         }
     }
 
-    pub fn build(self) -> Strangler {
+    /// Decides what the client gets to see when the strangled service can't be reached.
+    /// The default is an empty response with the status code from [`StranglerError::status_code`],
+    /// e.g. `502 Bad Gateway` or `504 Gateway Timeout`.
+    pub fn with_error_renderer<F>(self, error_renderer: F) -> Self
+    where
+        F: Fn(StranglerError) -> axum::response::Response + Send + Sync + 'static,
+    {
+        Self {
+            error_renderer: Arc::new(error_renderer),
+            ..self
+        }
+    }
+
+    /// How long to wait for a connection with the strangled service to be established.
+    /// Results in a `504 Gateway Timeout` by default. There is no timeout by default.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.timeouts.connect = Some(connect_timeout);
+        self
+    }
+
+    /// How long to wait for the strangled service to start responding, i.e. until its status and
+    /// headers are received.
+    /// Results in a `504 Gateway Timeout` by default. There is no timeout by default.
+    pub fn with_response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.timeouts.response = Some(response_timeout);
+        self
+    }
+
+    /// How long the strangled service gets to respond, including sending its whole body.
+    /// If the deadline passes before the status and headers are received, this results in a
+    /// `504 Gateway Timeout` by default, otherwise the response body is cut off.
+    /// There is no timeout by default.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.timeouts.request = Some(request_timeout);
+        self
+    }
+
+    /// How long the body of the strangled service's response may go without sending any data,
+    /// after which the response body is cut off.
+    /// There is no timeout by default.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.timeouts.idle = Some(idle_timeout);
+        self
+    }
+
+    /// How long to wait for the websocket connection with the strangled service to be set up.
+    /// Results in a `504 Gateway Timeout` by default. There is no timeout by default.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_handshake_timeout(
+        mut self,
+        web_socket_handshake_timeout: Duration,
+    ) -> Self {
+        self.timeouts.web_socket_handshake = Some(web_socket_handshake_timeout);
+        self
+    }
+
+    /// Ping both the client and the strangled service at this interval, to keep websocket
+    /// connections alive through proxies and load balancers. No pings are sent by default.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.web_socket_settings.ping_interval = Some(ping_interval);
+        self
+    }
+
+    /// Close websocket connections with `1001 Going Away` when neither the client nor the
+    /// strangled service sent a message for this long, pings and pongs don't count.
+    /// There is no timeout by default.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.web_socket_settings.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// The largest websocket message, in bytes, that the client and the strangled service may
+    /// send. A larger message closes the connection with `1009 Message Too Big`.
+    /// The default is tungstenite's, 64 MiB.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.web_socket_settings.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// The largest websocket frame, in bytes, that the client and the strangled service may
+    /// send. A larger frame closes the connection with `1009 Message Too Big`.
+    /// The default is tungstenite's, 16 MiB.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.web_socket_settings.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// Inspect, answer or rewrite the messages of websocket connections, see
+    /// [`crate::WebSocketMessageRouter`]. `new_message_router` makes the router of each connection
+    /// from its handshake request. By default messages are passed on as is.
+    #[cfg(feature = "websocket")]
+    pub fn with_web_socket_message_router<F, R>(mut self, new_message_router: F) -> Self
+    where
+        F: Fn(&axum::http::request::Parts) -> R + Send + Sync + 'static,
+        R: crate::WebSocketMessageRouter + 'static,
+    {
+        self.web_socket_settings.message_router = Some(Arc::new(
+            move |parts: &axum::http::request::Parts| -> Box<dyn crate::WebSocketMessageRouter> {
+                Box::new(new_message_router(parts))
+            },
+        ));
+        self
+    }
+
+    /// Retry failed requests to the strangled service, see [`RetryPolicy`].
+    /// By default requests aren't retried.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy: Some(retry_policy),
+            ..self
+        }
+    }
+
+    /// Stop forwarding requests for a while when the strangled service keeps failing, see
+    /// [`CircuitBreaker`].
+    /// By default there is no circuit breaker.
+    pub fn with_circuit_breaker(self, circuit_breaker: CircuitBreaker) -> Self {
+        Self {
+            circuit_breaker: Some(circuit_breaker),
+            ..self
+        }
+    }
+
+    /// Change the path or query of requests before forwarding them, see [`RewriteRule`].
+    /// Rules are applied in the order they are added, to both plain requests and websocket
+    /// upgrades.
+    pub fn with_rewrite(mut self, rewrite_rule: RewriteRule) -> Self {
+        self.rewrite_rules.push(rewrite_rule);
+        self
+    }
+
+    /// Tell the strangled service who the original client was, see [`ForwardedHeaders`].
+    /// By default no forwarding headers are added.
+    pub fn with_forwarded_headers(self, forwarded_headers: ForwardedHeaders) -> Self {
+        Self {
+            forwarded_headers: Some(forwarded_headers),
+            ..self
+        }
+    }
+
+    /// Tweak the requests and responses of the strangled service, see [`StranglerInterceptor`].
+    /// Can be called several times, the interceptors run in the order they were added.
+    pub fn with_interceptor<I>(mut self, interceptor: I) -> Self
+    where
+        I: StranglerInterceptor + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Mirror the forwarded requests to another service, see [`Shadow`].
+    /// By default requests aren't mirrored.
+    pub fn with_shadow(self, shadow: Shadow) -> Self {
+        Self {
+            shadow: Some(shadow),
+            ..self
+        }
+    }
+
+    /// The default is `LoadBalancing::RoundRobin`.
+    pub fn with_load_balancing(self, load_balancing: LoadBalancing) -> Self {
+        Self {
+            load_balancing,
+            ..self
+        }
+    }
+
+    /// The default is `OutlierEjection::default()`.
+    pub fn with_outlier_ejection(self, outlier_ejection: OutlierEjection) -> Self {
+        Self {
+            outlier_ejection: Some(outlier_ejection),
+            ..self
+        }
+    }
+
+    /// Never stop sending requests to an instance of the strangled service, no matter how often
+    /// it fails.
+    pub fn without_outlier_ejection(self) -> Self {
+        Self {
+            outlier_ejection: None,
+            ..self
+        }
+    }
+
+    /// Actively probe the instances of the strangled service, see [`crate::HealthCheck`].
+    /// The probes run on the tokio runtime, so `build` has to be called from within one.
+    #[cfg(feature = "health-check")]
+    pub fn with_health_check(self, health_check: crate::HealthCheck) -> Self {
+        Self {
+            health_check: Some(health_check),
+            ..self
+        }
+    }
+
+    pub fn build(mut self) -> Strangler {
+        if let Some(base_path) = self.base_path.take() {
+            self.rewrite_rules.push(RewriteRule::add_prefix(base_path));
+        }
+
+        let upstreams = Pool::new(self.authorities, self.load_balancing, self.outlier_ejection);
+
+        let mut http_connector = hyper::client::HttpConnector::new();
+        http_connector.set_connect_timeout(self.timeouts.connect);
+        #[cfg(feature = "websocket")]
+        let web_socket_client =
+            WebSocketClient::new(&self.web_socket_scheme, http_connector.clone());
+
         let inner: Arc<dyn InnerStrangler + Send + Sync> = match self.http_scheme {
             HttpScheme::HTTP => {
                 let inner = InnerStranglerService::new(
-                    self.authority,
+                    upstreams,
                     self.http_scheme,
                     #[cfg(feature = "websocket")]
                     self.web_socket_scheme,
-                    hyper::Client::new(),
+                    hyper::Client::builder().build(http_connector),
                     self.rewrite_strangled_request_host_header,
-                );
+                )
+                .with_timeouts(self.timeouts)
+                .with_retry_policy(self.retry_policy)
+                .with_circuit_breaker(self.circuit_breaker.map(Breaker::new))
+                .with_rewrite_rules(self.rewrite_rules)
+                .with_forwarded_headers(self.forwarded_headers)
+                .with_interceptors(self.interceptors)
+                .with_shadow(self.shadow);
+                #[cfg(feature = "websocket")]
+                let inner = inner
+                    .with_web_socket_client(web_socket_client)
+                    .with_web_socket_settings(self.web_socket_settings);
+                #[cfg(feature = "health-check")]
+                if let Some(health_check) = self.health_check {
+                    inner.spawn_health_checks(health_check);
+                }
                 Arc::new(inner)
             }
             #[cfg(feature = "https")]
             HttpScheme::HTTPS => {
-                let https = hyper_tls::HttpsConnector::new();
+                http_connector.enforce_http(false);
+                let https = hyper_tls::HttpsConnector::new_with_connector(http_connector);
                 let client = hyper::Client::builder().build::<_, hyper::Body>(https);
                 let inner = InnerStranglerService::new(
-                    self.authority,
+                    upstreams,
                     self.http_scheme,
                     #[cfg(feature = "websocket")]
                     self.web_socket_scheme,
                     client,
                     self.rewrite_strangled_request_host_header,
-                );
+                )
+                .with_timeouts(self.timeouts)
+                .with_retry_policy(self.retry_policy)
+                .with_circuit_breaker(self.circuit_breaker.map(Breaker::new))
+                .with_rewrite_rules(self.rewrite_rules)
+                .with_forwarded_headers(self.forwarded_headers)
+                .with_interceptors(self.interceptors)
+                .with_shadow(self.shadow);
+                #[cfg(feature = "websocket")]
+                let inner = inner
+                    .with_web_socket_client(web_socket_client)
+                    .with_web_socket_settings(self.web_socket_settings);
+                #[cfg(feature = "health-check")]
+                if let Some(health_check) = self.health_check {
+                    inner.spawn_health_checks(health_check);
+                }
                 Arc::new(inner)
             }
         };
 
-        Strangler { inner }
+        Strangler {
+            inner,
+            error_renderer: self.error_renderer,
+        }
+    }
+}
+
+#[cfg(feature = "https")]
+fn https_scheme() -> Result<HttpScheme, InvalidBaseUri> {
+    Ok(HttpScheme::HTTPS)
+}
+
+#[cfg(not(feature = "https"))]
+fn https_scheme() -> Result<HttpScheme, InvalidBaseUri> {
+    Err(InvalidBaseUri::MissingFeature {
+        scheme: "https",
+        feature: "https",
+    })
+}
+
+#[cfg(any(
+    feature = "websocket-native-tls",
+    feature = "websocket-rustls-tls-native-roots",
+    feature = "websocket-rustls-tls-webpki-roots"
+))]
+fn wss_scheme() -> Result<WebSocketScheme, InvalidBaseUri> {
+    Ok(WebSocketScheme::WSS)
+}
+
+#[cfg(all(
+    feature = "websocket",
+    not(any(
+        feature = "websocket-native-tls",
+        feature = "websocket-rustls-tls-native-roots",
+        feature = "websocket-rustls-tls-webpki-roots"
+    ))
+))]
+fn wss_scheme() -> Result<WebSocketScheme, InvalidBaseUri> {
+    Err(InvalidBaseUri::MissingFeature {
+        scheme: "wss",
+        feature: "websocket-native-tls",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_uri_needs_scheme_and_authority() {
+        let err = |uri: &'static str| {
+            StranglerBuilder::from_base_uri(axum::http::Uri::from_static(uri))
+                .err()
+                .unwrap()
+        };
+
+        assert_eq!(err("/app"), InvalidBaseUri::MissingAuthority);
+        assert_eq!(err("127.0.0.1:3333"), InvalidBaseUri::MissingScheme);
+        assert_eq!(
+            err("ftp://legacy.internal"),
+            InvalidBaseUri::UnsupportedScheme("ftp".to_owned())
+        );
+        assert_eq!(
+            err("http://legacy.internal/app?a=b"),
+            InvalidBaseUri::UnexpectedQuery
+        );
+        #[cfg(not(feature = "https"))]
+        assert_eq!(
+            err("https://legacy.internal"),
+            InvalidBaseUri::MissingFeature {
+                scheme: "https",
+                feature: "https"
+            }
+        );
     }
 }
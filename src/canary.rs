@@ -0,0 +1,256 @@
+use std::{
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::Request,
+    response::Response,
+    BoxError,
+};
+use tower_service::Service;
+
+use crate::{HashKey, Strangler};
+
+/// Which implementation served a request, added to the extensions of the responses of a
+/// [`Canary`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Implementation {
+    New,
+    Legacy,
+}
+
+/// Sends a percentage of the requests to the new implementation of a route, and the rest to the
+/// strangled service, so the new implementation can be rolled out gradually.
+/// The percentage can be changed at runtime through any clone of the `Canary`.
+/// ```rust
+/// use axum::routing::get;
+/// use axum_strangler::{Canary, HashKey, Strangler};
+///
+/// let new_implementation = axum::Router::new().route("/users", get(|| async { "users" }));
+/// let strangler = Strangler::new(axum::http::uri::Authority::from_static("127.0.0.1:3333"));
+/// let canary = Canary::new(new_implementation, strangler)
+///     .with_percentage(10)
+///     .with_stickiness(HashKey::Cookie("session".to_owned()));
+///
+/// let app = axum::Router::new().fallback(canary.clone());
+/// // Later on, e.g. from an admin endpoint:
+/// canary.set_percentage(50);
+/// ```
+#[derive(Clone)]
+pub struct Canary<N, L = Strangler> {
+    new: N,
+    legacy: L,
+    stickiness: Option<HashKey>,
+    state: Arc<CanaryState>,
+}
+
+#[derive(Default)]
+struct CanaryState {
+    percentage: AtomicU8,
+    served_by_new: AtomicU64,
+    served_by_legacy: AtomicU64,
+}
+
+impl<N, L> Canary<N, L> {
+    /// Sends all requests to `legacy` until the percentage is raised.
+    pub fn new(new: N, legacy: L) -> Self {
+        Self {
+            new,
+            legacy,
+            stickiness: None,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn with_percentage(self, percentage: u8) -> Self {
+        self.set_percentage(percentage);
+        self
+    }
+
+    /// Requests with the same value for `key` always go to the same implementation, as long as
+    /// the percentage doesn't change, so users don't flip between implementations.
+    /// Requests without the key are assigned randomly, which is also the default.
+    pub fn with_stickiness(self, key: HashKey) -> Self {
+        Self {
+            stickiness: Some(key),
+            ..self
+        }
+    }
+
+    /// Changes the percentage of requests that go to the new implementation, for all clones of
+    /// this `Canary`. Values above 100 are treated as 100.
+    pub fn set_percentage(&self, percentage: u8) {
+        self.state
+            .percentage
+            .store(percentage.min(100), Ordering::Relaxed);
+    }
+
+    pub fn percentage(&self) -> u8 {
+        self.state.percentage.load(Ordering::Relaxed)
+    }
+
+    /// How many requests each implementation served so far.
+    pub fn served_by(&self, implementation: Implementation) -> u64 {
+        match implementation {
+            Implementation::New => self.state.served_by_new.load(Ordering::Relaxed),
+            Implementation::Legacy => self.state.served_by_legacy.load(Ordering::Relaxed),
+        }
+    }
+
+    fn choose(&self, req: &Request<Body>) -> Implementation {
+        let bucket = match self
+            .stickiness
+            .as_ref()
+            .and_then(|key| key.extract(req.headers()))
+        {
+            Some(key) => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish() % 100) as u8
+            }
+            None => fastrand::u8(0..100),
+        };
+        if bucket < self.percentage() {
+            Implementation::New
+        } else {
+            Implementation::Legacy
+        }
+    }
+}
+
+impl<N, L, NewResBody, LegacyResBody> Service<Request<Body>> for Canary<N, L>
+where
+    N: Service<Request<Body>, Response = axum::http::Response<NewResBody>> + Clone + Send + 'static,
+    N::Future: Send,
+    L: Service<Request<Body>, Response = axum::http::Response<LegacyResBody>>
+        + Clone
+        + Send
+        + 'static,
+    L::Error: Into<N::Error>,
+    L::Future: Send,
+    NewResBody: HttpBody<Data = Bytes> + Send + 'static,
+    NewResBody::Error: Into<BoxError>,
+    LegacyResBody: HttpBody<Data = Bytes> + Send + 'static,
+    LegacyResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = N::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let implementation = self.choose(&req);
+        tracing::debug!(?implementation, uri = %req.uri(), "canary chose implementation");
+
+        let state = self.state.clone();
+        match implementation {
+            Implementation::New => {
+                let mut new = self.new.clone();
+                Box::pin(async move {
+                    std::future::poll_fn(|cx| new.poll_ready(cx)).await?;
+                    let mut response = new.call(req).await?.map(axum::body::boxed);
+                    state.served_by_new.fetch_add(1, Ordering::Relaxed);
+                    response.extensions_mut().insert(implementation);
+                    Ok(response)
+                })
+            }
+            Implementation::Legacy => {
+                let mut legacy = self.legacy.clone();
+                Box::pin(async move {
+                    std::future::poll_fn(|cx| legacy.poll_ready(cx))
+                        .await
+                        .map_err(Into::into)?;
+                    let mut response = legacy
+                        .call(req)
+                        .await
+                        .map_err(Into::into)?
+                        .map(axum::body::boxed);
+                    state.served_by_legacy.fetch_add(1, Ordering::Relaxed);
+                    response.extensions_mut().insert(implementation);
+                    Ok(response)
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    async fn canary(mock_server: &MockServer) -> Canary<axum::Router> {
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        Canary::new(
+            axum::Router::new().route("/", get(|| async { "new" })),
+            strangler,
+        )
+    }
+
+    async fn served_by(canary: &Canary<axum::Router>, user: Option<&str>) -> Implementation {
+        let mut req = Request::get("/");
+        if let Some(user) = user {
+            req = req.header("x-user-id", user);
+        }
+        let response = canary
+            .clone()
+            .call(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        *response.extensions().get::<Implementation>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn splits_by_percentage() {
+        let mock_server = MockServer::start().await;
+        let canary = canary(&mock_server).await;
+
+        assert_eq!(served_by(&canary, None).await, Implementation::Legacy);
+        canary.set_percentage(100);
+        assert_eq!(served_by(&canary, None).await, Implementation::New);
+
+        assert_eq!(canary.served_by(Implementation::New), 1);
+        assert_eq!(canary.served_by(Implementation::Legacy), 1);
+    }
+
+    #[tokio::test]
+    async fn sticks_to_an_implementation_per_user() {
+        let mock_server = MockServer::start().await;
+        let canary = canary(&mock_server)
+            .await
+            .with_percentage(50)
+            .with_stickiness(HashKey::Header(axum::http::HeaderName::from_static(
+                "x-user-id",
+            )));
+
+        for user in ["alice", "bob", "carol"] {
+            let first = served_by(&canary, Some(user)).await;
+            for _ in 0..5 {
+                assert_eq!(served_by(&canary, Some(user)).await, first);
+            }
+        }
+    }
+}
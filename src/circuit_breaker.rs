@@ -0,0 +1,313 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Stops forwarding requests to the strangled service for a while once it keeps failing,
+/// answering with `503 Service Unavailable` and a `Retry-After` header instead.
+///
+/// A request counts as failed when the strangled service couldn't be reached, or answered with
+/// a `5xx` status code.
+/// Once the cool-down has passed, a single request is let through: if that one succeeds the
+/// circuit closes again, otherwise it stays open for another cool-down.
+/// ```rust
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_circuit_breaker(
+///     axum_strangler::CircuitBreaker::new()
+///         .with_consecutive_failures(10)
+///         .with_error_rate(0.5, 20, std::time::Duration::from_secs(10)),
+/// )
+/// .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    consecutive_failures: Option<u32>,
+    error_rate: Option<ErrorRate>,
+    cool_down: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ErrorRate {
+    threshold: f64,
+    min_requests: u32,
+    window: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreaker {
+    /// Opens after 5 consecutive failures, and tries again after 30 seconds.
+    pub fn new() -> Self {
+        Self {
+            consecutive_failures: Some(5),
+            error_rate: None,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+
+    /// Open the circuit after this many failures in a row.
+    pub fn with_consecutive_failures(self, consecutive_failures: u32) -> Self {
+        Self {
+            consecutive_failures: Some(consecutive_failures),
+            ..self
+        }
+    }
+
+    /// Don't open the circuit based on consecutive failures, e.g. to only use the error rate.
+    pub fn without_consecutive_failures(self) -> Self {
+        Self {
+            consecutive_failures: None,
+            ..self
+        }
+    }
+
+    /// Open the circuit once the fraction of failed requests within `window` reaches `threshold`
+    /// (between `0.0` and `1.0`), as long as there were at least `min_requests`.
+    pub fn with_error_rate(self, threshold: f64, min_requests: u32, window: Duration) -> Self {
+        Self {
+            error_rate: Some(ErrorRate {
+                threshold,
+                min_requests,
+                window,
+            }),
+            ..self
+        }
+    }
+
+    /// How long the circuit stays open before a request is let through again.
+    pub fn with_cool_down(self, cool_down: Duration) -> Self {
+        Self { cool_down, ..self }
+    }
+}
+
+/// The state of the circuit breaker around the strangled service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are forwarded as usual.
+    Closed,
+    /// Requests are refused without forwarding them.
+    Open,
+    /// The cool-down has passed, and a single request is let through to see whether the strangled
+    /// service has recovered.
+    HalfOpen,
+}
+
+/// The shortest wait before trying again while a trial request is in flight, `Retry-After` has
+/// whole seconds.
+const MIN_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+pub(crate) struct Breaker {
+    config: CircuitBreaker,
+    state: Mutex<BreakerState>,
+}
+
+struct BreakerState {
+    opened_at: Option<Instant>,
+    /// When the trial request that's in flight while the circuit is half-open was let through.
+    trial_started_at: Option<Instant>,
+    consecutive_failures: u32,
+    window_start: Instant,
+    window_requests: u32,
+    window_failures: u32,
+}
+
+/// Has to be used to report the outcome of a request that was let through by the breaker.
+pub(crate) struct Permit<'a> {
+    breaker: &'a Breaker,
+    trial: bool,
+    recorded: bool,
+}
+
+impl Breaker {
+    pub(crate) fn new(config: CircuitBreaker) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState {
+                opened_at: None,
+                trial_started_at: None,
+                consecutive_failures: 0,
+                window_start: Instant::now(),
+                window_requests: 0,
+                window_failures: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        let state = self.state.lock().unwrap();
+        match state.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.config.cool_down => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Returns how long the caller should wait before trying again when the circuit is open.
+    pub(crate) fn acquire(&self) -> Result<Permit<'_>, Duration> {
+        let mut state = self.state.lock().unwrap();
+        let trial = match state.opened_at {
+            None => false,
+            Some(opened_at) => {
+                let elapsed = opened_at.elapsed();
+                if elapsed < self.config.cool_down {
+                    return Err(self.config.cool_down - elapsed);
+                }
+                // If the trial fails the circuit opens again, so requests are rejected for at
+                // least the cool down, counted from when the trial started. The trial can take
+                // longer than the cool down, so callers wait at least a second, not zero.
+                if let Some(trial_started_at) = state.trial_started_at {
+                    return Err(self
+                        .config
+                        .cool_down
+                        .saturating_sub(trial_started_at.elapsed())
+                        .max(MIN_RETRY_AFTER));
+                }
+                state.trial_started_at = Some(Instant::now());
+                true
+            }
+        };
+
+        Ok(Permit {
+            breaker: self,
+            trial,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, trial: bool, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        if trial {
+            state.trial_started_at = None;
+        }
+
+        if let Some(error_rate) = self.config.error_rate {
+            if state.window_start.elapsed() >= error_rate.window {
+                state.window_start = Instant::now();
+                state.window_requests = 0;
+                state.window_failures = 0;
+            }
+            state.window_requests += 1;
+            if !success {
+                state.window_failures += 1;
+            }
+        }
+
+        if success {
+            state.consecutive_failures = 0;
+            if trial {
+                tracing::info!("strangled service recovered, closing circuit");
+                state.opened_at = None;
+                state.window_requests = 0;
+                state.window_failures = 0;
+            }
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        if trial {
+            tracing::warn!("strangled service is still failing, keeping circuit open");
+            state.opened_at = Some(Instant::now());
+            return;
+        }
+
+        let too_many_consecutive_failures = self
+            .config
+            .consecutive_failures
+            .is_some_and(|threshold| state.consecutive_failures >= threshold);
+        let error_rate_too_high = self.config.error_rate.is_some_and(|error_rate| {
+            state.window_requests >= error_rate.min_requests
+                && f64::from(state.window_failures) / f64::from(state.window_requests)
+                    >= error_rate.threshold
+        });
+        if state.opened_at.is_none() && (too_many_consecutive_failures || error_rate_too_high) {
+            tracing::warn!(
+                consecutive_failures = state.consecutive_failures,
+                "strangled service keeps failing, opening circuit"
+            );
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+impl<'a> Permit<'a> {
+    pub(crate) fn record(mut self, success: bool) {
+        self.recorded = true;
+        self.breaker.record(self.trial, success);
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        // The request was cancelled before it completed, so we don't know anything new, but
+        // another request should be allowed to try.
+        if !self.recorded && self.trial {
+            self.breaker.state.lock().unwrap().trial_started_at = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = Breaker::new(CircuitBreaker::new().with_consecutive_failures(2));
+
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.acquire().unwrap().record(true);
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.acquire().is_err());
+    }
+
+    #[test]
+    fn opens_on_error_rate() {
+        let breaker = Breaker::new(
+            CircuitBreaker::new()
+                .without_consecutive_failures()
+                .with_error_rate(0.5, 4, Duration::from_secs(60)),
+        );
+
+        breaker.acquire().unwrap().record(false);
+        breaker.acquire().unwrap().record(true);
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.acquire().unwrap().record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn half_opens_after_cool_down() {
+        let breaker = Breaker::new(
+            CircuitBreaker::new()
+                .with_consecutive_failures(1)
+                .with_cool_down(Duration::from_millis(10)),
+        );
+
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        let trial = breaker.acquire().unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(breaker.acquire().err().unwrap(), Duration::from_secs(1));
+        // The trial takes longer than the cool down.
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.acquire().err().unwrap(), Duration::from_secs(1));
+        trial.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
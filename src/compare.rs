@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+
+use axum::{
+    body::Bytes,
+    http::{header::HeaderName, HeaderMap, StatusCode},
+};
+use serde_json::Value;
+
+/// A response of either the strangled service or the new implementation, as seen by a
+/// [`ResponseComparator`].
+/// The body is cut off at the shadow's maximum body size.
+#[derive(Clone, Debug)]
+pub struct CapturedResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Bytes,
+}
+
+impl CapturedResponse {
+    /// A response with the given parts, e.g. to test a [`ResponseComparator`].
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        Self {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The body of the response, at most the shadow's maximum body size.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+}
+
+/// A way in which the response of the new implementation differs from the strangled service's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// What differs, e.g. `status`, `header content-type` or `body $.user.name`.
+    pub location: String,
+    /// The value in the strangled service's response, e.g. a status code or the JSON of a field,
+    /// `missing` for a field that isn't there.
+    pub legacy: String,
+    /// The value in the new implementation's response, in the same form as `legacy`.
+    pub new: String,
+}
+
+/// Compares the responses of the strangled service and the new implementation of mirrored
+/// requests, see [`crate::Shadow::with_comparator`].
+pub trait ResponseComparator: Send + Sync {
+    /// Returns all the differences, an empty list means the responses match.
+    fn compare(&self, legacy: &CapturedResponse, new: &CapturedResponse) -> Vec<Difference>;
+}
+
+/// Compares the status, the selected headers and the body of the responses.
+/// Bodies that are both valid JSON are compared field by field, other bodies byte by byte.
+/// ```rust
+/// use axum_strangler::JsonComparator;
+///
+/// let comparator = JsonComparator::new()
+///     .with_header(axum::http::header::CONTENT_TYPE)
+///     .ignore_field("id")
+///     .ignore_field("updated_at")
+///     .with_unordered_arrays();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct JsonComparator {
+    headers: Vec<HeaderName>,
+    ignored_fields: HashSet<String>,
+    unordered_arrays: bool,
+}
+
+impl JsonComparator {
+    /// Compares the status and the body, but no headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also compare the values of the header `name`.
+    pub fn with_header(mut self, name: HeaderName) -> Self {
+        self.headers.push(name);
+        self
+    }
+
+    /// Don't compare object fields called `name`, at any depth, e.g. timestamps or generated ids.
+    pub fn ignore_field(mut self, name: impl Into<String>) -> Self {
+        self.ignored_fields.insert(name.into());
+        self
+    }
+
+    /// Arrays are equal when they contain the same elements, in any order.
+    pub fn with_unordered_arrays(self) -> Self {
+        Self {
+            unordered_arrays: true,
+            ..self
+        }
+    }
+
+    fn compare_json(
+        &self,
+        location: &str,
+        legacy: &Value,
+        new: &Value,
+        differences: &mut Vec<Difference>,
+    ) {
+        match (legacy, new) {
+            (Value::Object(legacy_fields), Value::Object(new_fields)) => {
+                let names: std::collections::BTreeSet<&String> = legacy_fields
+                    .keys()
+                    .chain(new_fields.keys())
+                    .filter(|name| !self.ignored_fields.contains(*name))
+                    .collect();
+                for name in names {
+                    let location = format!("{}.{}", location, name);
+                    match (legacy_fields.get(name), new_fields.get(name)) {
+                        (Some(legacy), Some(new)) => {
+                            self.compare_json(&location, legacy, new, differences)
+                        }
+                        (legacy, new) => differences.push(Difference {
+                            location,
+                            legacy: legacy.map_or_else(|| "missing".to_owned(), Value::to_string),
+                            new: new.map_or_else(|| "missing".to_owned(), Value::to_string),
+                        }),
+                    }
+                }
+            }
+            (Value::Array(legacy_items), Value::Array(new_items)) if self.unordered_arrays => {
+                if self.same_items(legacy_items, new_items) {
+                    return;
+                }
+                differences.push(Difference {
+                    location: location.to_owned(),
+                    legacy: legacy.to_string(),
+                    new: new.to_string(),
+                });
+            }
+            (Value::Array(legacy_items), Value::Array(new_items))
+                if legacy_items.len() == new_items.len() =>
+            {
+                for (i, (legacy, new)) in legacy_items.iter().zip(new_items).enumerate() {
+                    self.compare_json(&format!("{}[{}]", location, i), legacy, new, differences);
+                }
+            }
+            (legacy, new) if legacy != new => differences.push(Difference {
+                location: location.to_owned(),
+                legacy: legacy.to_string(),
+                new: new.to_string(),
+            }),
+            _ => {}
+        }
+    }
+
+    fn json_eq(&self, legacy: &Value, new: &Value) -> bool {
+        let mut differences = Vec::new();
+        self.compare_json("$", legacy, new, &mut differences);
+        differences.is_empty()
+    }
+
+    /// Whether every item has an equal counterpart in the other array.
+    fn same_items(&self, legacy: &[Value], new: &[Value]) -> bool {
+        if legacy.len() != new.len() {
+            return false;
+        }
+        let mut unmatched: Vec<&Value> = new.iter().collect();
+        legacy.iter().all(|legacy| {
+            match unmatched.iter().position(|new| self.json_eq(legacy, new)) {
+                Some(i) => {
+                    unmatched.swap_remove(i);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+impl ResponseComparator for JsonComparator {
+    fn compare(&self, legacy: &CapturedResponse, new: &CapturedResponse) -> Vec<Difference> {
+        let mut differences = Vec::new();
+
+        if legacy.status != new.status {
+            differences.push(Difference {
+                location: "status".to_owned(),
+                legacy: legacy.status.to_string(),
+                new: new.status.to_string(),
+            });
+        }
+
+        for name in &self.headers {
+            let values = |headers: &HeaderMap| {
+                headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let (legacy_values, new_values) = (values(&legacy.headers), values(&new.headers));
+            if legacy_values != new_values {
+                differences.push(Difference {
+                    location: format!("header {}", name),
+                    legacy: legacy_values,
+                    new: new_values,
+                });
+            }
+        }
+
+        match (
+            serde_json::from_slice::<Value>(&legacy.body),
+            serde_json::from_slice::<Value>(&new.body),
+        ) {
+            (Ok(legacy), Ok(new)) => self.compare_json("body $", &legacy, &new, &mut differences),
+            _ if legacy.body != new.body => differences.push(Difference {
+                location: "body".to_owned(),
+                legacy: String::from_utf8_lossy(&legacy.body).into_owned(),
+                new: String::from_utf8_lossy(&new.body).into_owned(),
+            }),
+            _ => {}
+        }
+
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, body: &'static str) -> CapturedResponse {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        CapturedResponse::new(
+            StatusCode::from_u16(status).unwrap(),
+            headers,
+            Bytes::from_static(body.as_bytes()),
+        )
+    }
+
+    #[test]
+    fn compares_json_bodies_field_by_field() {
+        let comparator = JsonComparator::new().ignore_field("updated_at");
+
+        let differences = comparator.compare(
+            &response(
+                200,
+                r#"{"id": 1, "name": "a", "tags": [1, 2], "updated_at": 10}"#,
+            ),
+            &response(
+                200,
+                r#"{"name": "b", "tags": [1, 2], "updated_at": 20, "id": 1}"#,
+            ),
+        );
+        assert_eq!(
+            differences,
+            [Difference {
+                location: "body $.name".to_owned(),
+                legacy: "\"a\"".to_owned(),
+                new: "\"b\"".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unordered_arrays() {
+        let legacy = response(200, r#"[{"id": 1, "at": 1}, {"id": 2, "at": 2}]"#);
+        let new = response(200, r#"[{"id": 2, "at": 3}, {"id": 1, "at": 4}]"#);
+
+        let comparator = JsonComparator::new().ignore_field("at");
+        assert_eq!(comparator.compare(&legacy, &new).len(), 2);
+
+        let comparator = comparator.with_unordered_arrays();
+        assert!(comparator.compare(&legacy, &new).is_empty());
+    }
+
+    #[test]
+    fn compares_status_headers_and_plain_bodies() {
+        let comparator = JsonComparator::new().with_header(axum::http::header::CONTENT_TYPE);
+
+        let mut new = response(404, "not found");
+        new.headers
+            .insert("content-type", "text/plain".parse().unwrap());
+        let locations: Vec<String> = comparator
+            .compare(&response(200, "found"), &new)
+            .into_iter()
+            .map(|difference| difference.location)
+            .collect();
+        assert_eq!(locations, ["status", "header content-type", "body"]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use axum::http::request::Parts;
+
+use crate::{HashKey, RegisteredRoute, RouteState};
+
+/// Decides per request whether a [`crate::RouteSwitch`] sends it to the new implementation, to
+/// the strangled service, or to both, e.g. based on a feature flag for the user or tenant.
+/// ```rust
+/// use axum::http::request::Parts;
+/// use axum_strangler::{RouteState, RoutingDecider};
+///
+/// struct TenantFlag;
+///
+/// #[axum::async_trait]
+/// impl RoutingDecider for TenantFlag {
+///     async fn decide(&self, parts: &Parts) -> RouteState {
+///         match parts.headers.get("x-tenant") {
+///             Some(tenant) if tenant == "acme" => RouteState::New,
+///             _ => RouteState::Legacy,
+///         }
+///     }
+/// }
+/// ```
+#[axum::async_trait]
+pub trait RoutingDecider: Send + Sync {
+    async fn decide(&self, parts: &Parts) -> RouteState;
+}
+
+/// Always the same decision.
+#[axum::async_trait]
+impl RoutingDecider for RouteState {
+    async fn decide(&self, _parts: &Parts) -> RouteState {
+        *self
+    }
+}
+
+/// Follows the state of the route in its [`crate::RouteRegistry`].
+#[axum::async_trait]
+impl RoutingDecider for RegisteredRoute {
+    async fn decide(&self, _parts: &Parts) -> RouteState {
+        self.state()
+    }
+}
+
+/// Decides based on the value of a header or cookie, e.g. one set by a feature-flag proxy.
+/// ```rust
+/// use axum_strangler::{FlagDecider, HashKey, RouteState};
+///
+/// let decider = FlagDecider::new(HashKey::Cookie("new-checkout".to_owned()), RouteState::Legacy)
+///     .with_value("on", RouteState::New)
+///     .with_value("shadow", RouteState::Shadow);
+/// ```
+#[derive(Clone, Debug)]
+pub struct FlagDecider {
+    key: HashKey,
+    values: HashMap<String, RouteState>,
+    default: RouteState,
+}
+
+impl FlagDecider {
+    /// Decides `default` for requests without the header or cookie, or with an unknown value.
+    pub fn new(key: HashKey, default: RouteState) -> Self {
+        Self {
+            key,
+            values: HashMap::new(),
+            default,
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<String>, state: RouteState) -> Self {
+        self.values.insert(value.into(), state);
+        self
+    }
+}
+
+#[axum::async_trait]
+impl RoutingDecider for FlagDecider {
+    async fn decide(&self, parts: &Parts) -> RouteState {
+        self.key
+            .extract(&parts.headers)
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .and_then(|value| self.values.get(value.trim()))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Decides based on a fixed map of path prefixes, the longest matching prefix wins.
+/// ```rust
+/// use axum_strangler::{RouteState, StaticDecider};
+///
+/// let decider = StaticDecider::new(RouteState::Legacy)
+///     .with_prefix("/users", RouteState::New)
+///     .with_prefix("/users/export", RouteState::Shadow);
+/// ```
+#[derive(Clone, Debug)]
+pub struct StaticDecider {
+    prefixes: Vec<(String, RouteState)>,
+    default: RouteState,
+}
+
+impl StaticDecider {
+    /// Decides `default` for paths that don't match any prefix.
+    pub fn new(default: RouteState) -> Self {
+        Self {
+            prefixes: Vec::new(),
+            default,
+        }
+    }
+
+    /// Matches paths that are `prefix`, or start with `prefix` followed by a `/`.
+    pub fn with_prefix(mut self, prefix: impl Into<String>, state: RouteState) -> Self {
+        self.prefixes
+            .push((prefix.into().trim_end_matches('/').to_owned(), state));
+        self
+    }
+}
+
+#[axum::async_trait]
+impl RoutingDecider for StaticDecider {
+    async fn decide(&self, parts: &Parts) -> RouteState {
+        let path = parts.uri.path();
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, state)| *state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    fn parts(req: axum::http::request::Builder) -> Parts {
+        req.body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn flag_decider() {
+        let decider = FlagDecider::new(
+            HashKey::Header(axum::http::HeaderName::from_static("x-flag")),
+            RouteState::Legacy,
+        )
+        .with_value("on", RouteState::New);
+
+        let on = parts(Request::get("/").header("x-flag", "on"));
+        assert_eq!(decider.decide(&on).await, RouteState::New);
+        let unknown = parts(Request::get("/").header("x-flag", "maybe"));
+        assert_eq!(decider.decide(&unknown).await, RouteState::Legacy);
+        assert_eq!(
+            decider.decide(&parts(Request::get("/"))).await,
+            RouteState::Legacy
+        );
+
+        let decider = FlagDecider::new(HashKey::Cookie("flag".to_owned()), RouteState::Legacy)
+            .with_value("shadow", RouteState::Shadow);
+        let cookie = parts(Request::get("/").header("cookie", "a=b; flag=shadow"));
+        assert_eq!(decider.decide(&cookie).await, RouteState::Shadow);
+    }
+
+    #[tokio::test]
+    async fn static_decider_picks_the_longest_prefix() {
+        let decider = StaticDecider::new(RouteState::Legacy)
+            .with_prefix("/users", RouteState::New)
+            .with_prefix("/users/export/", RouteState::Shadow);
+
+        for (uri, state) in [
+            ("/users/1", RouteState::New),
+            ("/users/export", RouteState::Shadow),
+            ("/usersettings", RouteState::Legacy),
+        ] {
+            assert_eq!(decider.decide(&parts(Request::get(uri))).await, state);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use axum::{http::StatusCode, response::IntoResponse};
+
+/// Everything that can go wrong while forwarding a request to the strangled service.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StranglerError {
+    /// No connection could be established with the strangled service.
+    Connect(hyper::Error),
+    /// The TLS handshake with the strangled service failed.
+    Tls(Box<dyn std::error::Error + Send + Sync>),
+    /// No connection could be established with the strangled service within the connect timeout.
+    ConnectTimeout(hyper::Error),
+    /// The strangled service didn't start responding within the response timeout.
+    ResponseTimeout,
+    /// The strangled service didn't finish its response within the request timeout.
+    RequestTimeout,
+    /// The strangled service didn't send any data within the idle timeout.
+    IdleTimeout,
+    /// The uri to reach the strangled service could not be constructed.
+    InvalidUri(axum::http::Error),
+    /// Sending or receiving a body to or from the strangled service failed.
+    Body(hyper::Error),
+    /// Reading the client's request body failed, e.g. because the client disconnected.
+    ClientBody(hyper::Error),
+    /// Any other failure while talking to the strangled service.
+    Upstream(hyper::Error),
+    /// The response of the strangled service could not be turned into a response for the client.
+    InvalidResponse(axum::http::Error),
+    /// The circuit breaker is open, so the request wasn't forwarded to the strangled service.
+    CircuitOpen {
+        /// How long it takes before requests are let through again.
+        retry_after: std::time::Duration,
+    },
+    /// The websocket connection with the strangled service could not be set up.
+    #[cfg(feature = "websocket")]
+    WebSocketUpgrade(tokio_tungstenite::tungstenite::Error),
+    /// The websocket handshake with the strangled service didn't finish within the handshake timeout.
+    #[cfg(feature = "websocket")]
+    WebSocketHandshakeTimeout,
+}
+
+impl StranglerError {
+    /// The status code that is returned to the client by default.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            StranglerError::ConnectTimeout(_)
+            | StranglerError::ResponseTimeout
+            | StranglerError::RequestTimeout
+            | StranglerError::IdleTimeout => StatusCode::GATEWAY_TIMEOUT,
+            StranglerError::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            StranglerError::ClientBody(_) => StatusCode::BAD_REQUEST,
+            StranglerError::Connect(_)
+            | StranglerError::Tls(_)
+            | StranglerError::InvalidUri(_)
+            | StranglerError::Body(_)
+            | StranglerError::Upstream(_)
+            | StranglerError::InvalidResponse(_) => StatusCode::BAD_GATEWAY,
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketUpgrade(_) => StatusCode::BAD_GATEWAY,
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketHandshakeTimeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+impl From<hyper::Error> for StranglerError {
+    fn from(e: hyper::Error) -> Self {
+        if e.is_connect() {
+            #[cfg(any(
+                feature = "https",
+                feature = "websocket-native-tls",
+                feature = "websocket-rustls-tls-native-roots",
+                feature = "websocket-rustls-tls-webpki-roots"
+            ))]
+            if is_tls_error(&e) {
+                return StranglerError::Tls(Box::new(e));
+            }
+            if is_timeout_error(&e) {
+                return StranglerError::ConnectTimeout(e);
+            }
+            StranglerError::Connect(e)
+        } else if e.is_body_write_aborted() || e.is_incomplete_message() {
+            StranglerError::Body(e)
+        } else {
+            StranglerError::Upstream(e)
+        }
+    }
+}
+
+fn is_timeout_error(e: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if e.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(any(
+    feature = "https",
+    feature = "websocket-native-tls",
+    feature = "websocket-rustls-tls-native-roots",
+    feature = "websocket-rustls-tls-webpki-roots"
+))]
+fn is_tls_error(e: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        if is_tls_library_error(e) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// The TLS libraries' errors can also be wrapped in an io error, rustls' always are.
+#[cfg(any(
+    feature = "https",
+    feature = "websocket-native-tls",
+    feature = "websocket-rustls-tls-native-roots",
+    feature = "websocket-rustls-tls-webpki-roots"
+))]
+fn is_tls_library_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    #[cfg(any(feature = "https", feature = "websocket-native-tls"))]
+    if e.is::<native_tls::Error>() {
+        return true;
+    }
+    #[cfg(any(
+        feature = "websocket-rustls-tls-native-roots",
+        feature = "websocket-rustls-tls-webpki-roots"
+    ))]
+    if e.is::<rustls::Error>() {
+        return true;
+    }
+    e.downcast_ref::<std::io::Error>()
+        .and_then(std::io::Error::get_ref)
+        .is_some_and(|e| is_tls_library_error(e))
+}
+
+#[cfg(feature = "websocket")]
+impl From<tokio_tungstenite::tungstenite::Error> for StranglerError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        match e {
+            tokio_tungstenite::tungstenite::Error::Tls(e) => StranglerError::Tls(Box::new(e)),
+            e => StranglerError::WebSocketUpgrade(e),
+        }
+    }
+}
+
+impl std::fmt::Display for StranglerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StranglerError::Connect(e) => {
+                write!(f, "could not connect to the strangled service: {}", e)
+            }
+            StranglerError::Tls(e) => {
+                write!(f, "tls handshake with the strangled service failed: {}", e)
+            }
+            StranglerError::ConnectTimeout(e) => {
+                write!(f, "connecting to the strangled service timed out: {}", e)
+            }
+            StranglerError::ResponseTimeout => {
+                write!(f, "the strangled service didn't respond in time")
+            }
+            StranglerError::RequestTimeout => {
+                write!(
+                    f,
+                    "the strangled service didn't finish its response in time"
+                )
+            }
+            StranglerError::IdleTimeout => {
+                write!(f, "the strangled service didn't send any data in time")
+            }
+            StranglerError::InvalidUri(e) => {
+                write!(f, "invalid uri for the strangled service: {}", e)
+            }
+            StranglerError::Body(e) => {
+                write!(
+                    f,
+                    "body error while talking to the strangled service: {}",
+                    e
+                )
+            }
+            StranglerError::ClientBody(e) => {
+                write!(f, "could not read the request body: {}", e)
+            }
+            StranglerError::Upstream(e) => {
+                write!(f, "error while talking to the strangled service: {}", e)
+            }
+            StranglerError::InvalidResponse(e) => {
+                write!(f, "invalid response from the strangled service: {}", e)
+            }
+            StranglerError::CircuitOpen { retry_after } => write!(
+                f,
+                "the strangled service keeps failing, not forwarding requests for another {:?}",
+                retry_after
+            ),
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketUpgrade(e) => write!(
+                f,
+                "could not set up a websocket connection with the strangled service: {}",
+                e
+            ),
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketHandshakeTimeout => write!(
+                f,
+                "the websocket handshake with the strangled service didn't finish in time"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StranglerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StranglerError::Connect(e)
+            | StranglerError::ConnectTimeout(e)
+            | StranglerError::Body(e)
+            | StranglerError::ClientBody(e)
+            | StranglerError::Upstream(e) => Some(e),
+            StranglerError::Tls(e) => Some(e.as_ref()),
+            StranglerError::InvalidUri(e) | StranglerError::InvalidResponse(e) => Some(e),
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketUpgrade(e) => Some(e),
+            StranglerError::ResponseTimeout
+            | StranglerError::RequestTimeout
+            | StranglerError::IdleTimeout
+            | StranglerError::CircuitOpen { .. } => None,
+            #[cfg(feature = "websocket")]
+            StranglerError::WebSocketHandshakeTimeout => None,
+        }
+    }
+}
+
+/// Why a base uri can't be used for the strangled service, see [`crate::Strangler::from_base_uri`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidBaseUri {
+    /// The base uri has no scheme, like `http` or `https`.
+    MissingScheme,
+    /// The base uri has no authority, i.e. no host and port.
+    MissingAuthority,
+    /// Only `http` and `https` are supported.
+    UnsupportedScheme(String),
+    /// The scheme of the base uri needs a feature of this crate that isn't enabled.
+    MissingFeature {
+        scheme: &'static str,
+        feature: &'static str,
+    },
+    /// The base uri has a query, which can't be joined with the query of a request.
+    UnexpectedQuery,
+}
+
+impl std::fmt::Display for InvalidBaseUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidBaseUri::MissingScheme => write!(f, "the base uri has no scheme"),
+            InvalidBaseUri::MissingAuthority => write!(f, "the base uri has no host"),
+            InvalidBaseUri::UnsupportedScheme(scheme) => write!(
+                f,
+                "unsupported scheme `{}`, expected `http` or `https`",
+                scheme
+            ),
+            InvalidBaseUri::MissingFeature { scheme, feature } => write!(
+                f,
+                "strangling a service over `{}` requires the `{}` feature of axum-strangler",
+                scheme, feature
+            ),
+            InvalidBaseUri::UnexpectedQuery => write!(f, "the base uri can't have a query"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidBaseUri {}
+
+/// The default rendering: an empty response with the status code from [`StranglerError::status_code`],
+/// with a `Retry-After` header when the circuit is open.
+impl IntoResponse for StranglerError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            StranglerError::CircuitOpen { retry_after } => {
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                (
+                    self.status_code(),
+                    [(axum::http::header::RETRY_AFTER, seconds.to_string())],
+                )
+                    .into_response()
+            }
+            _ => self.status_code().into_response(),
+        }
+    }
+}
+
+/// Turns a [`StranglerError`] into the response that is sent to the client.
+pub(crate) type ErrorRenderer =
+    std::sync::Arc<dyn Fn(StranglerError) -> axum::response::Response + Send + Sync>;
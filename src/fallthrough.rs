@@ -0,0 +1,45 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+/// Returned from a handler behind a [`crate::StranglerLayer`] to let the strangled service handle
+/// the request after all, e.g. when the new handler only supports some of the query parameters of
+/// an endpoint.
+/// The original request, including its body, is forwarded to the strangled service.
+/// Without a `StranglerLayer` this is just a `404 Not Found`.
+/// ```rust
+/// use axum::{extract::Query, routing::get};
+/// use axum_strangler::{StrangleFallthrough, Strangler, StranglerLayer};
+/// use std::collections::HashMap;
+///
+/// async fn search(
+///     Query(params): Query<HashMap<String, String>>,
+/// ) -> Result<String, StrangleFallthrough> {
+///     match params.get("q") {
+///         Some(q) if params.len() == 1 => Ok(format!("Results for {}", q)),
+///         _ => Err(StrangleFallthrough),
+///     }
+/// }
+///
+/// let strangler = Strangler::new(axum::http::uri::Authority::from_static("127.0.0.1:3333"));
+/// let app = axum::Router::new()
+///     .route("/search", get(search))
+///     .layer(StranglerLayer::new(strangler));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct StrangleFallthrough;
+
+impl StrangleFallthrough {
+    pub(crate) fn is_set(response: &Response) -> bool {
+        response.extensions().get::<StrangleFallthrough>().is_some()
+    }
+}
+
+impl IntoResponse for StrangleFallthrough {
+    fn into_response(self) -> Response {
+        let mut response = StatusCode::NOT_FOUND.into_response();
+        response.extensions_mut().insert(self);
+        response
+    }
+}
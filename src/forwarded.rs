@@ -0,0 +1,294 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header::HeaderName, uri::Scheme, HeaderMap, HeaderValue, Request, Version},
+};
+use ipnet::IpNet;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+const VIA: HeaderName = HeaderName::from_static("via");
+
+/// Tells the strangled service who the original client was, with the `X-Forwarded-For`,
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` headers, the `Forwarded` header from RFC 7239 and
+/// the `Via` header.
+///
+/// The client's ip address is taken from axum's `ConnectInfo<SocketAddr>`, so the app has to be
+/// served with `into_make_service_with_connect_info::<SocketAddr>()`.
+/// Forwarding headers that were sent along with the request are only kept, and appended to, if
+/// the client is one of the trusted proxies. Otherwise they are replaced, so clients can't spoof
+/// them.
+/// ```rust
+/// use axum_strangler::ForwardedHeaders;
+///
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_forwarded_headers(
+///     ForwardedHeaders::new().with_trusted_proxy("10.0.0.0/8".parse().unwrap()),
+/// )
+/// .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ForwardedHeaders {
+    x_forwarded: bool,
+    forwarded: bool,
+    via: Option<String>,
+    proto: Option<Scheme>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl Default for ForwardedHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ForwardedHeaders {
+    /// Sets all the headers, and trusts no proxies.
+    pub fn new() -> Self {
+        Self {
+            x_forwarded: true,
+            forwarded: true,
+            via: Some("axum-strangler".to_owned()),
+            proto: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// Don't set the `X-Forwarded-*` headers.
+    pub fn without_x_forwarded(self) -> Self {
+        Self {
+            x_forwarded: false,
+            ..self
+        }
+    }
+
+    /// Don't set the `Forwarded` header.
+    pub fn without_forwarded(self) -> Self {
+        Self {
+            forwarded: false,
+            ..self
+        }
+    }
+
+    /// Don't set the `Via` header.
+    pub fn without_via(self) -> Self {
+        Self { via: None, ..self }
+    }
+
+    /// How the strangler calls itself in the `Via` header, the default is `axum-strangler`.
+    pub fn with_via_pseudonym(self, pseudonym: impl Into<String>) -> Self {
+        Self {
+            via: Some(pseudonym.into()),
+            ..self
+        }
+    }
+
+    /// The scheme the app itself is served with, e.g. `https` when it terminates TLS. The default
+    /// is the scheme of the request's uri, which is only known for HTTP/2, and `http` otherwise.
+    /// An `X-Forwarded-Proto` header of a trusted proxy is kept as is.
+    pub fn with_proto(self, proto: Scheme) -> Self {
+        Self {
+            proto: Some(proto),
+            ..self
+        }
+    }
+
+    /// Keep the forwarding headers of requests coming from this range of addresses.
+    pub fn with_trusted_proxy(mut self, trusted_proxy: IpNet) -> Self {
+        self.trusted_proxies.push(trusted_proxy);
+        self
+    }
+
+    pub(crate) fn apply<B>(&self, req: &mut Request<B>) {
+        let client_ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let trusted = client_ip.is_some_and(|client_ip| {
+            self.trusted_proxies
+                .iter()
+                .any(|trusted_proxy| trusted_proxy.contains(&client_ip))
+        });
+        let proto = match &self.proto {
+            Some(proto) => proto.as_str().to_owned(),
+            None => req.uri().scheme_str().unwrap_or("http").to_owned(),
+        };
+        let host = req
+            .headers()
+            .get(axum::http::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+            .map(ToOwned::to_owned);
+        let version = req.version();
+        let headers = req.headers_mut();
+
+        if !trusted {
+            headers.remove(X_FORWARDED_FOR);
+            headers.remove(X_FORWARDED_PROTO);
+            headers.remove(X_FORWARDED_HOST);
+            headers.remove(FORWARDED);
+        }
+
+        if self.x_forwarded {
+            if let Some(client_ip) = client_ip {
+                append(headers, X_FORWARDED_FOR, &client_ip.to_string());
+            }
+            if !headers.contains_key(X_FORWARDED_PROTO) {
+                insert(headers, X_FORWARDED_PROTO, &proto);
+            }
+            if let Some(host) = &host {
+                if !headers.contains_key(X_FORWARDED_HOST) {
+                    insert(headers, X_FORWARDED_HOST, host);
+                }
+            }
+        }
+
+        if self.forwarded {
+            let mut forwarded = match client_ip {
+                Some(IpAddr::V4(ip)) => format!("for={}", ip),
+                Some(IpAddr::V6(ip)) => format!("for=\"[{}]\"", ip),
+                None => "for=unknown".to_owned(),
+            };
+            if let Some(host) = &host {
+                forwarded.push_str(&format!(";host=\"{}\"", host));
+            }
+            forwarded.push_str(&format!(";proto={}", proto));
+            append(headers, FORWARDED, &forwarded);
+        }
+
+        if let Some(pseudonym) = &self.via {
+            let version = match version {
+                Version::HTTP_09 => "0.9",
+                Version::HTTP_10 => "1.0",
+                Version::HTTP_2 => "2",
+                Version::HTTP_3 => "3",
+                _ => "1.1",
+            };
+            append(headers, VIA, &format!("{} {}", version, pseudonym));
+        }
+    }
+}
+
+/// Adds `value` to the comma separated list in the header, or sets the header.
+fn append(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    let existing: Vec<&str> = headers
+        .get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if existing.is_empty() {
+        insert(headers, name, value);
+    } else {
+        let value = format!("{}, {}", existing.join(", "), value);
+        insert(headers, name, &value);
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => {
+            tracing::debug!(header = %name, "invalid forwarding header value, not setting it")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(client: &str) -> Request<()> {
+        let mut req = Request::get("/hello")
+            .header("host", "example.com")
+            .header("x-forwarded-for", "1.2.3.4")
+            .header("x-forwarded-proto", "https")
+            .header("forwarded", "for=1.2.3.4;proto=https")
+            .header("via", "1.1 cdn")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(client.parse::<SocketAddr>().unwrap()));
+        req
+    }
+
+    #[test]
+    fn replaces_headers_of_untrusted_clients() {
+        let mut req = request("192.168.1.10:5000");
+        ForwardedHeaders::new().apply(&mut req);
+
+        let headers = req.headers();
+        assert_eq!(headers["x-forwarded-for"], "192.168.1.10");
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(headers["x-forwarded-host"], "example.com");
+        assert_eq!(
+            headers["forwarded"],
+            "for=192.168.1.10;host=\"example.com\";proto=http"
+        );
+        assert_eq!(headers["via"], "1.1 cdn, 1.1 axum-strangler");
+    }
+
+    #[test]
+    fn appends_to_headers_of_trusted_proxies() {
+        let mut req = request("10.0.0.1:5000");
+        ForwardedHeaders::new()
+            .with_trusted_proxy("10.0.0.0/8".parse().unwrap())
+            .without_via()
+            .apply(&mut req);
+
+        let headers = req.headers();
+        assert_eq!(headers["x-forwarded-for"], "1.2.3.4, 10.0.0.1");
+        assert_eq!(headers["x-forwarded-proto"], "https");
+        assert_eq!(
+            headers["forwarded"],
+            "for=1.2.3.4;proto=https, for=10.0.0.1;host=\"example.com\";proto=http"
+        );
+        assert_eq!(headers["via"], "1.1 cdn");
+    }
+
+    #[test]
+    fn quotes_ipv6_addresses() {
+        let mut req = request("[::1]:5000");
+        ForwardedHeaders::new()
+            .without_x_forwarded()
+            .apply(&mut req);
+
+        let headers = req.headers();
+        assert!(!headers.contains_key("x-forwarded-for"));
+        assert_eq!(
+            headers["forwarded"],
+            "for=\"[::1]\";host=\"example.com\";proto=http"
+        );
+    }
+
+    #[test]
+    fn uses_the_configured_proto() {
+        let mut req = request("192.168.1.10:5000");
+        ForwardedHeaders::new()
+            .with_proto(Scheme::HTTPS)
+            .apply(&mut req);
+
+        let headers = req.headers();
+        assert_eq!(headers["x-forwarded-proto"], "https");
+        assert_eq!(
+            headers["forwarded"],
+            "for=192.168.1.10;host=\"example.com\";proto=https"
+        );
+
+        let mut req = request("10.0.0.1:5000");
+        req.headers_mut()
+            .insert("x-forwarded-proto", HeaderValue::from_static("http"));
+        ForwardedHeaders::new()
+            .with_trusted_proxy("10.0.0.0/8".parse().unwrap())
+            .with_proto(Scheme::HTTPS)
+            .apply(&mut req);
+
+        assert_eq!(req.headers()["x-forwarded-proto"], "http");
+    }
+}
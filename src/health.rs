@@ -0,0 +1,71 @@
+use axum::{http::StatusCode, response::IntoResponse};
+
+use crate::{CircuitState, UpstreamHealth};
+
+/// A snapshot of how the strangled service is doing, see [`crate::Strangler::health`].
+#[derive(Clone, Debug)]
+pub struct StranglerHealth {
+    pub(crate) circuit: CircuitState,
+    pub(crate) upstreams: Vec<UpstreamHealth>,
+}
+
+impl StranglerHealth {
+    /// The state of the circuit breaker, this is always [`CircuitState::Closed`] if no circuit
+    /// breaker was configured.
+    pub fn circuit(&self) -> CircuitState {
+        self.circuit
+    }
+
+    /// The state of every instance of the strangled service.
+    pub fn upstreams(&self) -> &[UpstreamHealth] {
+        &self.upstreams
+    }
+
+    /// Whether or not requests are currently being forwarded to the strangled service.
+    pub fn is_healthy(&self) -> bool {
+        self.circuit != CircuitState::Open
+            && self
+                .upstreams
+                .iter()
+                .any(|upstream| upstream.is_healthy() && !upstream.is_ejected())
+    }
+}
+
+/// Responds with `200 OK` when healthy, `503 Service Unavailable` otherwise, listing the state of
+/// every instance of the strangled service.
+/// This allows to directly return the health from e.g. a `/healthz` route:
+/// ```rust
+/// let strangler = axum_strangler::Strangler::new(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// );
+/// let router = axum::Router::new().route(
+///     "/healthz",
+///     axum::routing::get({
+///         let strangler = strangler.clone();
+///         move || async move { strangler.health() }
+///     }),
+/// ).fallback(strangler);
+/// ```
+impl IntoResponse for StranglerHealth {
+    fn into_response(self) -> axum::response::Response {
+        let status = if self.is_healthy() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        let mut body = format!("circuit: {:?}\n", self.circuit);
+        for upstream in &self.upstreams {
+            let state = if !upstream.is_healthy() {
+                "unhealthy"
+            } else if upstream.is_ejected() {
+                "ejected"
+            } else {
+                "healthy"
+            };
+            body.push_str(&format!("{}: {}\n", upstream.authority(), state));
+        }
+
+        (status, body).into_response()
+    }
+}
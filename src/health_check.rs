@@ -0,0 +1,149 @@
+use std::{sync::Weak, time::Duration};
+
+use axum::http::{
+    uri::{PathAndQuery, Scheme},
+    StatusCode, Uri,
+};
+
+use crate::upstream::Pool;
+
+/// Periodically probes every instance of the strangled service with a `GET` request, and stops
+/// forwarding requests to the instances that don't respond with the expected status.
+/// If all instances are unhealthy, requests are spread over all of them anyway.
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_health_check(axum_strangler::HealthCheck::new("/healthz"))
+/// .build();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    path: String,
+    expected_status: StatusCode,
+    interval: Duration,
+    timeout: Duration,
+    healthy_threshold: u32,
+    unhealthy_threshold: u32,
+}
+
+impl HealthCheck {
+    /// Probes `path` every 10 seconds, expecting a `200 OK` within 2 seconds.
+    /// `path` goes through the rewrite rules like the paths of forwarded requests, so it's
+    /// relative to the base path of [`crate::StranglerBuilder::from_base_uri`].
+    /// An instance becomes unhealthy after 3 failed probes in a row, and healthy again after 2
+    /// successful probes in a row.
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            expected_status: StatusCode::OK,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn with_expected_status(self, expected_status: StatusCode) -> Self {
+        Self {
+            expected_status,
+            ..self
+        }
+    }
+
+    pub fn with_interval(self, interval: Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// How many probes in a row need to succeed for an unhealthy instance to become healthy.
+    pub fn with_healthy_threshold(self, healthy_threshold: u32) -> Self {
+        Self {
+            healthy_threshold,
+            ..self
+        }
+    }
+
+    /// How many probes in a row need to fail for a healthy instance to become unhealthy.
+    pub fn with_unhealthy_threshold(self, unhealthy_threshold: u32) -> Self {
+        Self {
+            unhealthy_threshold,
+            ..self
+        }
+    }
+}
+
+/// Probes the instances in the background for as long as the pool is alive.
+///
+/// # Panics
+/// If not called from within a tokio runtime.
+pub(crate) fn spawn<C>(
+    health_check: HealthCheck,
+    path_and_query: PathAndQuery,
+    upstreams: Weak<Pool>,
+    http_client: hyper::Client<C>,
+    scheme: Scheme,
+) where
+    C: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(health_check.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // The pool is never held while probing, so the task stops once the strangler is dropped.
+            let uris = match upstreams.upgrade() {
+                Some(upstreams) => upstreams
+                    .authorities()
+                    .map(|authority| {
+                        Uri::builder()
+                            .scheme(scheme.clone())
+                            .authority(authority.clone())
+                            .path_and_query(path_and_query.clone())
+                            .build()
+                    })
+                    .collect::<Result<Vec<_>, _>>(),
+                None => return,
+            };
+            let uris = match uris {
+                Ok(uris) => uris,
+                Err(e) => {
+                    tracing::error!(error = %e, "invalid health check uri");
+                    return;
+                }
+            };
+
+            let (health_check, http_client, upstreams) = (&health_check, &http_client, &upstreams);
+            let probes = uris.into_iter().enumerate().map(|(index, uri)| async move {
+                let healthy =
+                    match tokio::time::timeout(health_check.timeout, http_client.get(uri.clone()))
+                        .await
+                    {
+                        Ok(Ok(response)) => response.status() == health_check.expected_status,
+                        Ok(Err(_)) | Err(_) => false,
+                    };
+                tracing::trace!(%uri, healthy, "probed strangled service");
+
+                if let Some(upstreams) = upstreams.upgrade() {
+                    upstreams.record_probe(
+                        index,
+                        healthy,
+                        health_check.healthy_threshold,
+                        health_check.unhealthy_threshold,
+                    );
+                }
+            });
+            futures_util::future::join_all(probes).await;
+        }
+    });
+}
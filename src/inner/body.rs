@@ -0,0 +1,146 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::body::{Body, Bytes, HttpBody};
+use futures_util::StreamExt;
+
+use crate::{upstream::Selected, StranglerError};
+
+pub(crate) enum Buffered {
+    /// The whole body fit within the limit.
+    Complete(Bytes),
+    /// The body was larger than the limit, this body yields everything that was read so far,
+    /// followed by the rest of the original body.
+    TooLarge(Body),
+}
+
+/// Reads the body into memory, as long as it's not larger than `limit`.
+pub(crate) async fn buffer_body(mut body: Body, limit: usize) -> Result<Buffered, StranglerError> {
+    let mut chunks: Vec<Bytes> = Vec::new();
+    let mut size = 0;
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(StranglerError::ClientBody)?;
+        size += chunk.len();
+        chunks.push(chunk);
+
+        if size > limit {
+            let read = futures_util::stream::iter(chunks.into_iter().map(Ok::<_, hyper::Error>));
+            return Ok(Buffered::TooLarge(Body::wrap_stream(read.chain(body))));
+        }
+    }
+
+    Ok(Buffered::Complete(match chunks.len() {
+        0 => Bytes::new(),
+        1 => chunks.pop().unwrap(),
+        _ => chunks.concat().into(),
+    }))
+}
+
+/// Creates a copy of the request, without the extensions.
+pub(crate) fn clone_request(
+    parts: &axum::http::request::Parts,
+    body: Bytes,
+) -> axum::http::Request<Body> {
+    let mut req = axum::http::Request::new(Body::from(body));
+    *req.method_mut() = parts.method.clone();
+    *req.uri_mut() = parts.uri.clone();
+    *req.version_mut() = parts.version;
+    *req.headers_mut() = parts.headers.clone();
+    req
+}
+
+/// Wraps the body of the strangled service's response, so the instance that sends it counts as
+/// an outstanding request until the body is done.
+pub(crate) struct UpstreamBody<B> {
+    inner: B,
+    upstream: Option<Selected>,
+}
+
+impl<B> UpstreamBody<B> {
+    pub(crate) fn new(inner: B, upstream: Option<Selected>) -> Self {
+        Self { inner, upstream }
+    }
+}
+
+impl<B> HttpBody for UpstreamBody<B>
+where
+    B: HttpBody + Unpin,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_data(cx);
+        if let Poll::Ready(None) = polled {
+            this.upstream = None;
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<axum::http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn buffers_small_bodies() {
+        let body = Body::wrap_stream(futures_util::stream::iter(vec![
+            Ok::<_, std::io::Error>("hello "),
+            Ok("world"),
+        ]));
+
+        match buffer_body(body, 11).await.unwrap() {
+            Buffered::Complete(bytes) => assert_eq!(bytes, "hello world"),
+            Buffered::TooLarge(_) => panic!("body should fit"),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_large_bodies_intact() {
+        let body = Body::wrap_stream(futures_util::stream::iter(vec![
+            Ok::<_, std::io::Error>("hello "),
+            Ok("world"),
+        ]));
+
+        match buffer_body(body, 3).await.unwrap() {
+            Buffered::Complete(_) => panic!("body shouldn't fit"),
+            Buffered::TooLarge(body) => {
+                assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello world")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_bodies_are_the_clients_fault() {
+        let body = Body::wrap_stream(futures_util::stream::iter(vec![
+            Ok::<_, std::io::Error>("hello "),
+            Err(std::io::ErrorKind::ConnectionReset.into()),
+        ]));
+
+        let e = buffer_body(body, 64).await.err().unwrap();
+        assert!(matches!(e, StranglerError::ClientBody(_)));
+        assert_eq!(e.status_code(), axum::http::StatusCode::BAD_REQUEST);
+    }
+}
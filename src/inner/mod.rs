@@ -1,10 +1,26 @@
 use axum::http::Uri;
 
-use crate::HttpScheme;
+use crate::{
+    circuit_breaker::Breaker,
+    rewrite::rewrite,
+    upstream::{Pool, Selected},
+    CircuitState, ForwardedHeaders, HttpScheme, RetryPolicy, RewriteRule, Shadow, StranglerError,
+    StranglerHealth, StranglerInterceptor,
+};
+
+use self::body::{buffer_body, clone_request, Buffered, UpstreamBody};
+use self::hop_by_hop::remove_hop_by_hop_headers;
+use self::timeout::TimeoutBody;
+pub(crate) use self::timeout::Timeouts;
+#[cfg(feature = "websocket")]
+pub(crate) use self::websocket::{WebSocketClient, WebSocketSettings};
 
 #[cfg(feature = "websocket")]
 use crate::WebSocketScheme;
 
+pub(crate) mod body;
+mod hop_by_hop;
+mod timeout;
 #[cfg(feature = "websocket")]
 mod websocket;
 
@@ -16,7 +32,9 @@ pub(crate) trait InnerStrangler {
     async fn forward_call_to_strangled(
         &self,
         req: axum::http::Request<axum::body::Body>,
-    ) -> axum::response::Response;
+    ) -> Result<axum::response::Response, StranglerError>;
+
+    fn health(&self) -> StranglerHealth;
 }
 
 #[axum::async_trait]
@@ -26,68 +44,71 @@ where
 {
     async fn forward_call_to_strangled(
         &self,
-        req: axum::http::Request<axum::body::Body>,
-    ) -> axum::response::Response {
-        let mut req = match self.handle_websocket_upgrade_request(req).await {
-            Ok(r) => {
-                return r;
+        mut req: axum::http::Request<axum::body::Body>,
+    ) -> Result<axum::response::Response, StranglerError> {
+        let comparison = match &self.shadow {
+            Some(shadow) => {
+                let (mirrored, comparison) = shadow.mirror(req).await?;
+                req = mirrored;
+                comparison
             }
-            Err(r) => r,
+            None => None,
         };
 
-        let strangled_authority = self.strangled_authority.clone();
-        let strangled_scheme = self.get_http_scheme();
-        let uri = Uri::builder()
-            .scheme(strangled_scheme)
-            .authority(strangled_authority)
-            .path_and_query(req.uri().path_and_query().cloned().unwrap())
-            .build()
-            .unwrap();
-
-        if self.rewrite_strangled_request_host_header {
-            if let Some(host) = req.headers_mut().get_mut("host") {
-                *host =
-                    axum::http::HeaderValue::from_str(uri.authority().unwrap().as_str()).unwrap()
-            }
-        }
-
-        #[cfg(feature = "tracing-opentelemetry-text-map-propagation")]
-        {
-            req =
-                tracing_opentelemetry_text_map_propagation::inject_opentelemetry_context_into_request(
-                    req,
-                );
+        for interceptor in &self.interceptors {
+            interceptor.on_request(&mut req).await;
         }
 
-        *req.uri_mut() = uri;
-
-        let r = self.http_client.request(req).await.unwrap();
+        let mut response = self.forward_through_circuit_breaker(req).await?;
 
-        let mut response_builder = axum::response::Response::builder();
-        response_builder = response_builder.status(r.status());
-
-        if let Some(headers) = response_builder.headers_mut() {
-            *headers = r.headers().clone();
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.on_response(&mut response).await;
         }
+        Ok(match comparison {
+            Some(comparison) => comparison.capture(response),
+            None => response,
+        })
+    }
 
-        let response = response_builder
-            .body(axum::body::boxed(r))
-            .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR);
-
-        match response {
-            Ok(response) => response,
-            Err(_) => todo!(),
+    fn health(&self) -> StranglerHealth {
+        StranglerHealth {
+            circuit: self
+                .circuit_breaker
+                .as_ref()
+                .map_or(CircuitState::Closed, Breaker::state),
+            upstreams: self.upstreams.health(),
         }
     }
 }
 
 pub(crate) struct InnerStranglerService<C> {
-    strangled_authority: axum::http::uri::Authority,
+    upstreams: std::sync::Arc<Pool>,
     strangled_http_scheme: HttpScheme,
     #[cfg(feature = "websocket")]
     strangled_web_socket_scheme: WebSocketScheme,
     http_client: hyper::Client<C>,
     rewrite_strangled_request_host_header: bool,
+    timeouts: Timeouts,
+    retry_policy: Option<RetryPolicy>,
+    circuit_breaker: Option<Breaker>,
+    rewrite_rules: Vec<RewriteRule>,
+    forwarded_headers: Option<ForwardedHeaders>,
+    interceptors: Vec<std::sync::Arc<dyn StranglerInterceptor>>,
+    shadow: Option<Shadow>,
+    #[cfg(feature = "websocket")]
+    web_socket_client: WebSocketClient,
+    #[cfg(feature = "websocket")]
+    web_socket_settings: WebSocketSettings,
+}
+
+impl<C> InnerStranglerService<C> {
+    /// The path and query to request from the strangled service, after applying the rewrite rules.
+    fn strangled_path_and_query(
+        &self,
+        uri: &Uri,
+    ) -> Result<axum::http::uri::PathAndQuery, axum::http::Error> {
+        rewrite(&self.rewrite_rules, uri)
+    }
 }
 
 impl<C> InnerStranglerService<C>
@@ -95,19 +116,318 @@ where
     C: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
 {
     pub(crate) fn new(
-        strangled_authority: axum::http::uri::Authority,
+        upstreams: Pool,
         strangled_http_scheme: HttpScheme,
         #[cfg(feature = "websocket")] strangled_web_socket_scheme: WebSocketScheme,
         http_client: hyper::Client<C>,
         rewrite_strangled_request_host_header: bool,
     ) -> Self {
         Self {
-            strangled_authority,
+            upstreams: std::sync::Arc::new(upstreams),
             strangled_http_scheme,
-            #[cfg(feature = "websocket")]
-            strangled_web_socket_scheme,
             http_client,
             rewrite_strangled_request_host_header,
+            timeouts: Timeouts::default(),
+            retry_policy: None,
+            circuit_breaker: None,
+            rewrite_rules: Vec::new(),
+            forwarded_headers: None,
+            interceptors: Vec::new(),
+            shadow: None,
+            #[cfg(feature = "websocket")]
+            web_socket_client: WebSocketClient::new(
+                &strangled_web_socket_scheme,
+                hyper::client::HttpConnector::new(),
+            ),
+            #[cfg(feature = "websocket")]
+            web_socket_settings: WebSocketSettings::default(),
+            #[cfg(feature = "websocket")]
+            strangled_web_socket_scheme,
+        }
+    }
+
+    pub(crate) fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        Self { timeouts, ..self }
+    }
+
+    pub(crate) fn with_retry_policy(self, retry_policy: Option<RetryPolicy>) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// # Panics
+    /// If not called from within a tokio runtime.
+    #[cfg(feature = "health-check")]
+    pub(crate) fn spawn_health_checks(&self, health_check: crate::HealthCheck) {
+        // Probes go through the same rewrite rules as forwarded requests, so the probe path is
+        // relative to the base path as well.
+        let path_and_query = match health_check
+            .path()
+            .parse::<Uri>()
+            .map_err(axum::http::Error::from)
+            .and_then(|path| self.strangled_path_and_query(&path))
+        {
+            Ok(path_and_query) => path_and_query,
+            Err(e) => {
+                tracing::error!(error = %e, "invalid health check path");
+                return;
+            }
+        };
+        crate::health_check::spawn(
+            health_check,
+            path_and_query,
+            std::sync::Arc::downgrade(&self.upstreams),
+            self.http_client.clone(),
+            self.get_http_scheme(),
+        );
+    }
+
+    pub(crate) fn with_circuit_breaker(self, circuit_breaker: Option<Breaker>) -> Self {
+        Self {
+            circuit_breaker,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_rewrite_rules(self, rewrite_rules: Vec<RewriteRule>) -> Self {
+        Self {
+            rewrite_rules,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_forwarded_headers(
+        self,
+        forwarded_headers: Option<ForwardedHeaders>,
+    ) -> Self {
+        Self {
+            forwarded_headers,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_interceptors(
+        self,
+        interceptors: Vec<std::sync::Arc<dyn StranglerInterceptor>>,
+    ) -> Self {
+        Self {
+            interceptors,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_shadow(self, shadow: Option<Shadow>) -> Self {
+        Self { shadow, ..self }
+    }
+
+    #[cfg(feature = "websocket")]
+    pub(crate) fn with_web_socket_client(self, web_socket_client: WebSocketClient) -> Self {
+        Self {
+            web_socket_client,
+            ..self
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    pub(crate) fn with_web_socket_settings(self, web_socket_settings: WebSocketSettings) -> Self {
+        Self {
+            web_socket_settings,
+            ..self
+        }
+    }
+
+    async fn forward_through_circuit_breaker(
+        &self,
+        req: axum::http::Request<axum::body::Body>,
+    ) -> Result<axum::response::Response, StranglerError> {
+        let permit = match &self.circuit_breaker {
+            Some(circuit_breaker) => Some(
+                circuit_breaker
+                    .acquire()
+                    .map_err(|retry_after| StranglerError::CircuitOpen { retry_after })?,
+            ),
+            None => None,
+        };
+
+        let result = self.forward(req).await;
+
+        if let Some(permit) = permit {
+            // The client failing to send its body says nothing about the strangled service.
+            permit.record(match &result {
+                Ok(r) => !r.status().is_server_error(),
+                Err(e) => matches!(e, StranglerError::ClientBody(_)),
+            });
+        }
+        result
+    }
+
+    async fn forward(
+        &self,
+        mut req: axum::http::Request<axum::body::Body>,
+    ) -> Result<axum::response::Response, StranglerError> {
+        let deadline = self
+            .timeouts
+            .request
+            .map(|request_timeout| tokio::time::Instant::now() + request_timeout);
+
+        if let Some(forwarded_headers) = &self.forwarded_headers {
+            forwarded_headers.apply(&mut req);
+        }
+
+        let mut req = match self.handle_websocket_upgrade_request(req).await {
+            Ok(r) => {
+                return r;
+            }
+            Err(r) => r,
+        };
+        remove_hop_by_hop_headers(req.headers_mut());
+
+        #[cfg(feature = "tracing-opentelemetry-text-map-propagation")]
+        let req =
+            tracing_opentelemetry_text_map_propagation::inject_opentelemetry_context_into_request(
+                req,
+            );
+
+        let r = self.send_with_retries(req, deadline).await?;
+        let (mut parts, body) = r.into_parts();
+        remove_hop_by_hop_headers(&mut parts.headers);
+        let body = UpstreamBody::new(body, parts.extensions.remove::<Selected>());
+
+        let mut response_builder = axum::response::Response::builder();
+        response_builder = response_builder.status(parts.status);
+
+        if let Some(headers) = response_builder.headers_mut() {
+            *headers = parts.headers;
+        }
+
+        let body = if deadline.is_some() || self.timeouts.idle.is_some() {
+            axum::body::boxed(TimeoutBody::new(body, deadline, self.timeouts.idle))
+        } else {
+            axum::body::boxed(body)
+        };
+
+        response_builder
+            .body(body)
+            .map_err(StranglerError::InvalidResponse)
+    }
+
+    async fn send_with_retries(
+        &self,
+        req: axum::http::Request<axum::body::Body>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<hyper::Response<hyper::Body>, StranglerError> {
+        let retry_policy = match &self.retry_policy {
+            Some(retry_policy) if retry_policy.allows_method(req.method()) => retry_policy,
+            _ => return self.send(req, deadline, 1).await,
+        };
+
+        let (parts, body) = req.into_parts();
+        let body = match buffer_body(body, retry_policy.max_replay_body_size()).await? {
+            Buffered::Complete(body) => body,
+            Buffered::TooLarge(body) => {
+                tracing::debug!("request body is too large to be replayed, not retrying");
+                return self
+                    .send(axum::http::Request::from_parts(parts, body), deadline, 1)
+                    .await;
+            }
+        };
+
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .send(clone_request(&parts, body.clone()), deadline, attempt)
+                .await;
+            if attempt >= retry_policy.max_attempts() || !retry_policy.should_retry(&result) {
+                return result;
+            }
+
+            let backoff = retry_policy.backoff(attempt);
+            if deadline.is_some_and(|deadline| tokio::time::Instant::now() + backoff >= deadline) {
+                return result;
+            }
+            match &result {
+                Ok(r) => tracing::warn!(
+                    attempt,
+                    status = %r.status(),
+                    ?backoff,
+                    "strangled service returned a retryable status, retrying"
+                ),
+                Err(e) => tracing::warn!(
+                    attempt,
+                    error = %e,
+                    ?backoff,
+                    "request to strangled service failed, retrying"
+                ),
+            }
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send(
+        &self,
+        mut req: axum::http::Request<axum::body::Body>,
+        deadline: Option<tokio::time::Instant>,
+        attempt: u32,
+    ) -> Result<hyper::Response<hyper::Body>, StranglerError> {
+        let upstream = self.upstreams.select(req.headers());
+
+        let uri = Uri::builder()
+            .scheme(self.get_http_scheme())
+            .authority(upstream.authority().clone())
+            .path_and_query(
+                self.strangled_path_and_query(req.uri())
+                    .map_err(StranglerError::InvalidUri)?,
+            )
+            .build()
+            .map_err(StranglerError::InvalidUri)?;
+
+        if self.rewrite_strangled_request_host_header {
+            if let Some(host) = req.headers_mut().get_mut("host") {
+                *host = upstream.host().clone();
+            }
+        }
+
+        *req.uri_mut() = uri;
+
+        tracing::debug!(attempt, uri = %req.uri(), "forwarding request to strangled service");
+
+        let mut result = self.send_to_upstream(req, deadline).await;
+        upstream.record(matches!(&result, Ok(r) if !r.status().is_server_error()));
+        // The instance stays busy until it has sent the whole body.
+        if let Ok(r) = &mut result {
+            r.extensions_mut().insert(upstream);
+        }
+        result
+    }
+
+    async fn send_to_upstream(
+        &self,
+        req: axum::http::Request<axum::body::Body>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<hyper::Response<hyper::Body>, StranglerError> {
+        let response_deadline = self
+            .timeouts
+            .response
+            .map(|response_timeout| tokio::time::Instant::now() + response_timeout);
+        let first_byte_deadline = match (response_deadline, deadline) {
+            (Some(response_deadline), Some(deadline)) if deadline < response_deadline => {
+                Some((deadline, StranglerError::RequestTimeout))
+            }
+            (Some(response_deadline), _) => {
+                Some((response_deadline, StranglerError::ResponseTimeout))
+            }
+            (None, Some(deadline)) => Some((deadline, StranglerError::RequestTimeout)),
+            (None, None) => None,
+        };
+        match first_byte_deadline {
+            Some((at, timeout_error)) => tokio::time::timeout_at(at, self.http_client.request(req))
+                .await
+                .map_err(|_| timeout_error)?
+                .map_err(StranglerError::from),
+            None => Ok(self.http_client.request(req).await?),
         }
     }
 
@@ -115,7 +435,10 @@ where
     async fn handle_websocket_upgrade_request(
         &self,
         req: axum::http::Request<axum::body::Body>,
-    ) -> Result<axum::response::Response, axum::http::Request<axum::body::Body>> {
+    ) -> Result<
+        Result<axum::response::Response, StranglerError>,
+        axum::http::Request<axum::body::Body>,
+    > {
         Err(req)
     }
 
@@ -156,7 +479,7 @@ mod tests {
 
         let client = hyper::client::Client::new();
         let inner = InnerStranglerService::new(
-            authority,
+            Pool::from(authority),
             HttpScheme::HTTP,
             #[cfg(feature = "websocket")]
             crate::WebSocketScheme::WS,
@@ -175,7 +498,8 @@ mod tests {
             .forward_call_to_strangled(
                 dbg!(request_builder.body(axum::body::Body::empty())).unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
 
         assert_eq!(response.status(), axum::http::status::StatusCode::OK)
     }
@@ -199,7 +523,7 @@ mod tests {
 
         let client = hyper::client::Client::new();
         let inner = InnerStranglerService::new(
-            authority,
+            Pool::from(authority),
             HttpScheme::HTTP,
             #[cfg(feature = "websocket")]
             crate::WebSocketScheme::WS,
@@ -218,8 +542,239 @@ mod tests {
             .forward_call_to_strangled(
                 dbg!(request_builder.body(axum::body::Body::empty())).unwrap(),
             )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), axum::http::status::StatusCode::OK)
+    }
+
+    #[tokio::test]
+    async fn rewrites_path_and_query() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/app/v1/hello"))
+            .and(wiremock::matchers::query_param("name", "world"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
             .await;
 
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            mock_server.address().port()
+        ))
+        .unwrap();
+        let inner = InnerStranglerService::new(
+            Pool::from(authority),
+            HttpScheme::HTTP,
+            #[cfg(feature = "websocket")]
+            crate::WebSocketScheme::WS,
+            hyper::client::Client::new(),
+            false,
+        )
+        .with_rewrite_rules(vec![
+            RewriteRule::strip_prefix("/legacy"),
+            RewriteRule::add_prefix("/app/v1"),
+            RewriteRule::rename_query_param("n", "name"),
+        ]);
+
+        let response = inner
+            .forward_call_to_strangled(
+                axum::http::Request::get("/legacy/hello?n=world")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
         assert_eq!(response.status(), axum::http::status::StatusCode::OK)
     }
+
+    #[tokio::test]
+    async fn strips_hop_by_hop_headers() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/hello"))
+            .and(header("x-kept", "1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("connection", "x-upstream-hop")
+                    .insert_header("x-upstream-hop", "1")
+                    .insert_header("keep-alive", "timeout=5")
+                    .insert_header("x-kept", "1"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            mock_server.address().port()
+        ))
+        .unwrap();
+        let inner = InnerStranglerService::new(
+            Pool::from(authority),
+            HttpScheme::HTTP,
+            #[cfg(feature = "websocket")]
+            crate::WebSocketScheme::WS,
+            hyper::client::Client::new(),
+            false,
+        );
+
+        let response = inner
+            .forward_call_to_strangled(
+                axum::http::Request::get("/hello")
+                    .header("connection", "x-client-hop")
+                    .header("x-client-hop", "1")
+                    .header("proxy-authorization", "Basic Zm9vOmJhcg==")
+                    .header("te", "trailers")
+                    .header("x-kept", "1")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), axum::http::status::StatusCode::OK);
+        assert_eq!(response.headers()["x-kept"], "1");
+        for name in ["connection", "x-upstream-hop", "keep-alive"] {
+            assert!(!response.headers().contains_key(name), "{}", name);
+        }
+
+        let received = &mock_server.received_requests().await.unwrap()[0];
+        for name in ["x-client-hop", "proxy-authorization", "te"] {
+            assert!(
+                !received.headers.iter().any(|(key, _)| key.as_str() == name),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable_strangled_service_is_an_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        drop(listener);
+
+        let client = hyper::client::Client::new();
+        let inner = InnerStranglerService::new(
+            Pool::from(authority),
+            HttpScheme::HTTP,
+            #[cfg(feature = "websocket")]
+            crate::WebSocketScheme::WS,
+            client,
+            false,
+        );
+        let request = axum::http::Request::builder()
+            .method("GET")
+            .uri("http://something.com/hello")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let error = inner.forward_call_to_strangled(request).await.unwrap_err();
+
+        assert!(matches!(error, StranglerError::Connect(_)));
+        assert_eq!(
+            error.status_code(),
+            axum::http::status::StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_strangled_service_times_out() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500)),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            mock_server.address().port()
+        ))
+        .unwrap();
+
+        let client = hyper::client::Client::new();
+        let inner = InnerStranglerService::new(
+            Pool::from(authority),
+            HttpScheme::HTTP,
+            #[cfg(feature = "websocket")]
+            crate::WebSocketScheme::WS,
+            client,
+            false,
+        )
+        .with_timeouts(Timeouts {
+            response: Some(std::time::Duration::from_millis(50)),
+            ..Timeouts::default()
+        });
+        let request = axum::http::Request::builder()
+            .method("GET")
+            .uri("http://something.com/slow")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let error = inner.forward_call_to_strangled(request).await.unwrap_err();
+
+        assert!(matches!(error, StranglerError::ResponseTimeout));
+        assert_eq!(
+            error.status_code(),
+            axum::http::status::StatusCode::GATEWAY_TIMEOUT
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_responses() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            mock_server.address().port()
+        ))
+        .unwrap();
+
+        let client = hyper::client::Client::new();
+        let inner = InnerStranglerService::new(
+            Pool::from(authority),
+            HttpScheme::HTTP,
+            #[cfg(feature = "websocket")]
+            crate::WebSocketScheme::WS,
+            client,
+            false,
+        )
+        .with_retry_policy(Some(RetryPolicy::new(2)));
+        let request = || {
+            axum::http::Request::builder()
+                .method("GET")
+                .uri("http://something.com/flaky")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = inner.forward_call_to_strangled(request()).await.unwrap();
+
+        assert_eq!(response.status(), axum::http::status::StatusCode::OK);
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+    }
 }
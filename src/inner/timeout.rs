@@ -0,0 +1,161 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::body::{Bytes, HttpBody};
+use tokio::time::{Instant, Sleep};
+
+use crate::StranglerError;
+
+/// The timeouts that are applied when forwarding a request to the strangled service.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) response: Option<Duration>,
+    pub(crate) request: Option<Duration>,
+    pub(crate) idle: Option<Duration>,
+    #[cfg(feature = "websocket")]
+    pub(crate) web_socket_handshake: Option<Duration>,
+}
+
+/// Wraps the body of the strangled service's response, erroring out once the request deadline
+/// passes or when no data was received within the idle timeout.
+pub(crate) struct TimeoutBody<B> {
+    inner: B,
+    deadline: Option<Pin<Box<Sleep>>>,
+    idle_timeout: Option<Duration>,
+    idle: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B> TimeoutBody<B> {
+    pub(crate) fn new(inner: B, deadline: Option<Instant>, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            deadline: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline))),
+            idle_timeout,
+            idle: idle_timeout.map(|idle_timeout| Box::pin(tokio::time::sleep(idle_timeout))),
+        }
+    }
+
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Option<StranglerError> {
+        let elapsed = self
+            .deadline
+            .as_mut()
+            .is_some_and(|deadline| deadline.as_mut().poll(cx).is_ready());
+        elapsed.then_some(StranglerError::RequestTimeout)
+    }
+
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Option<StranglerError> {
+        let elapsed = self
+            .idle
+            .as_mut()
+            .is_some_and(|idle| idle.as_mut().poll(cx).is_ready());
+        elapsed.then_some(StranglerError::IdleTimeout)
+    }
+
+    fn reset_idle(&mut self) {
+        if let (Some(idle), Some(idle_timeout)) = (self.idle.as_mut(), self.idle_timeout) {
+            idle.as_mut().reset(Instant::now() + idle_timeout);
+        }
+    }
+}
+
+impl<B> HttpBody for TimeoutBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Into<axum::BoxError>,
+{
+    type Data = Bytes;
+    type Error = axum::BoxError;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(e) = this.poll_deadline(cx) {
+            return Poll::Ready(Some(Err(e.into())));
+        }
+        match Pin::new(&mut this.inner).poll_data(cx) {
+            Poll::Ready(data) => {
+                this.reset_idle();
+                Poll::Ready(data.map(|data| data.map_err(Into::into)))
+            }
+            Poll::Pending => match this.poll_idle(cx) {
+                Some(e) => Poll::Ready(Some(Err(e.into()))),
+                None => Poll::Pending,
+            },
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<axum::http::HeaderMap>, Self::Error>> {
+        let this = self.get_mut();
+        if let Some(e) = this.poll_deadline(cx) {
+            return Poll::Ready(Err(e.into()));
+        }
+        match Pin::new(&mut this.inner).poll_trailers(cx) {
+            Poll::Ready(trailers) => Poll::Ready(trailers.map_err(Into::into)),
+            Poll::Pending => match this.poll_idle(cx) {
+                Some(e) => Poll::Ready(Err(e.into())),
+                None => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_body_times_out() {
+        let (_sender, body) = hyper::Body::channel();
+        let mut body = TimeoutBody::new(body, None, Some(Duration::from_millis(10)));
+
+        let e = body.data().await.unwrap().unwrap_err();
+
+        assert!(matches!(
+            e.downcast_ref::<StranglerError>(),
+            Some(StranglerError::IdleTimeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn body_times_out_at_deadline_even_when_data_keeps_coming() {
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            loop {
+                if sender.send_data(Bytes::from_static(b"a")).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        let mut body = TimeoutBody::new(
+            body,
+            Some(Instant::now() + Duration::from_millis(50)),
+            Some(Duration::from_millis(20)),
+        );
+
+        let e = loop {
+            if let Err(e) = body.data().await.unwrap() {
+                break e;
+            }
+        };
+
+        assert!(matches!(
+            e.downcast_ref::<StranglerError>(),
+            Some(StranglerError::RequestTimeout)
+        ));
+    }
+}
@@ -1,20 +1,42 @@
 use axum::extract::ws::Message as AxumMessage;
 use axum::{
     extract::{ws::WebSocket, RequestParts},
-    http::Uri,
+    http::{header, request::Parts, HeaderMap, StatusCode, Uri},
 };
-use futures_util::{SinkExt, StreamExt};
-use tokio_tungstenite::tungstenite::protocol::Message as TungsteniteMessage;
+use std::{pin::Pin, sync::Arc, time::Duration};
 
-use crate::inner::InnerStranglerService;
-use crate::WebSocketScheme;
+use hyper::client::HttpConnector;
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        error::ProtocolError,
+        handshake::{client::generate_key, derive_accept_key},
+        protocol::{
+            frame::coding::CloseCode, CloseFrame, Message as TungsteniteMessage, Role,
+            WebSocketConfig,
+        },
+    },
+    WebSocketStream,
+};
+
+use crate::inner::{
+    body::UpstreamBody, hop_by_hop::remove_hop_by_hop_headers, InnerStranglerService,
+};
+use crate::{
+    upstream::Selected, MessageAction, StranglerError, WebSocketMessageRouter, WebSocketScheme,
+};
 
 #[cfg(feature = "websocket")]
 impl<C> InnerStranglerService<C> {
     pub(super) async fn handle_websocket_upgrade_request(
         &self,
         req: axum::http::Request<axum::body::Body>,
-    ) -> Result<axum::response::Response, axum::http::Request<axum::body::Body>> {
+    ) -> Result<
+        Result<axum::response::Response, StranglerError>,
+        axum::http::Request<axum::body::Body>,
+    > {
         let mut request_parts = RequestParts::new(req);
         let wsu: axum::extract::ws::WebSocketUpgrade = match request_parts.extract().await {
             Ok(wsu) => wsu,
@@ -26,29 +48,183 @@ impl<C> InnerStranglerService<C> {
         };
 
         let req: Result<axum::http::Request<axum::body::Body>, _> = request_parts.extract().await;
-        let req = req.unwrap();
+        let (parts, _) = req.unwrap().into_parts();
 
-        let strangled_authority = self.strangled_authority.clone();
+        let upstream = self.upstreams.select(&parts.headers);
+        // The handshake is a plain http request, so a refused one can be passed on as is.
         let strangled_scheme = match self.strangled_web_socket_scheme {
-            WebSocketScheme::WS => "ws",
+            WebSocketScheme::WS => "http",
             #[cfg(any(
                 feature = "websocket-native-tls",
                 feature = "websocket-rustls-tls-native-roots",
                 feature = "websocket-rustls-tls-webpki-roots"
             ))]
-            WebSocketScheme::WSS => "wss",
+            WebSocketScheme::WSS => "https",
         };
 
-        let uri = Uri::builder()
-            .authority(strangled_authority)
+        let path_and_query = match self.strangled_path_and_query(&parts.uri) {
+            Ok(path_and_query) => path_and_query,
+            Err(e) => return Ok(Err(StranglerError::InvalidUri(e))),
+        };
+        let uri = match Uri::builder()
+            .authority(upstream.authority().clone())
             .scheme(strangled_scheme)
-            .path_and_query(req.uri().path_and_query().cloned().unwrap())
+            .path_and_query(path_and_query)
             .build()
-            .unwrap();
+        {
+            Ok(uri) => uri,
+            Err(e) => return Ok(Err(StranglerError::InvalidUri(e))),
+        };
+
+        let key = generate_key();
+        let mut handshake_request = match hyper::Request::get(uri)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "13")
+            .header(header::SEC_WEBSOCKET_KEY, &key)
+            .body(hyper::Body::empty())
+        {
+            Ok(handshake_request) => handshake_request,
+            Err(e) => return Ok(Err(StranglerError::InvalidUri(e))),
+        };
+        handshake_request
+            .headers_mut()
+            .extend(self.handshake_request_headers(&parts.headers));
+
+        let handshake = self.web_socket_client.request(handshake_request);
+        let handshake_response = match self.timeouts.web_socket_handshake {
+            Some(handshake_timeout) => {
+                match tokio::time::timeout(handshake_timeout, handshake).await {
+                    Ok(handshake_response) => handshake_response,
+                    Err(_) => {
+                        upstream.record(false);
+                        return Ok(Err(StranglerError::WebSocketHandshakeTimeout));
+                    }
+                }
+            }
+            None => handshake.await,
+        };
+        let handshake_response = match handshake_response {
+            Ok(handshake_response) => handshake_response,
+            Err(e) => {
+                upstream.record(false);
+                return Ok(Err(e.into()));
+            }
+        };
+        if handshake_response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            upstream.record(!handshake_response.status().is_server_error());
+            return Ok(Ok(rejected_handshake_response(
+                handshake_response,
+                upstream,
+            )));
+        }
+        let accept = derive_accept_key(key.as_bytes());
+        if handshake_response
+            .headers()
+            .get(header::SEC_WEBSOCKET_ACCEPT)
+            .map_or(true, |value| value != accept.as_str())
+        {
+            upstream.record(false);
+            return Ok(Err(tungstenite::Error::Protocol(
+                ProtocolError::SecWebSocketAcceptKeyMismatch,
+            )
+            .into()));
+        }
+
+        let handshake_headers = handshake_response.headers().clone();
+        let connection = match hyper::upgrade::on(handshake_response).await {
+            Ok(upgraded) => {
+                upstream.record(true);
+                WebSocketStream::from_raw_socket(
+                    upgraded,
+                    Role::Client,
+                    Some(self.web_socket_settings.config()),
+                )
+                .await
+            }
+            Err(e) => {
+                upstream.record(false);
+                return Ok(Err(e.into()));
+            }
+        };
+
+        let settings = self.web_socket_settings.clone();
+        let wsu = match settings.max_message_size {
+            Some(max_message_size) => wsu.max_message_size(max_message_size),
+            None => wsu,
+        };
+        let wsu = match settings.max_frame_size {
+            Some(max_frame_size) => wsu.max_frame_size(max_frame_size),
+            None => wsu,
+        };
+        let wsu = match handshake_headers
+            .get(header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|protocol| protocol.to_str().ok())
+        {
+            Some(protocol) => wsu.protocols([protocol.to_owned()]),
+            None => wsu,
+        };
+        let message_router = settings
+            .message_router
+            .as_ref()
+            .map(|new_message_router| new_message_router(&parts));
+        let mut response = wsu.on_upgrade(move |socket| async move {
+            on_websocket_upgrade(socket, connection, settings, message_router).await;
+            // The instance stays busy for as long as the connection is open.
+            drop(upstream);
+        });
+        response
+            .headers_mut()
+            .extend(handshake_response_headers(handshake_headers));
+        Ok(Ok(response))
+    }
+
+    /// The headers of the client's handshake request that are forwarded to the strangled service,
+    /// the ones that belong to the client's own handshake are left out.
+    fn handshake_request_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        remove_hop_by_hop_headers(&mut headers);
+        for name in [
+            header::SEC_WEBSOCKET_KEY,
+            header::SEC_WEBSOCKET_VERSION,
+            // tungstenite doesn't support any extensions
+            header::SEC_WEBSOCKET_EXTENSIONS,
+        ] {
+            headers.remove(name);
+        }
+        if self.rewrite_strangled_request_host_header {
+            headers.remove(header::HOST);
+        }
+        headers
+    }
+}
 
-        let (connection, _) = tokio_tungstenite::connect_async(uri).await.unwrap();
-        Ok(wsu.on_upgrade(|socket| on_websocket_upgrade(socket, connection)))
+/// The headers of the strangled service's handshake response that are passed on to the client,
+/// e.g. `Set-Cookie`. The subprotocol is passed on through the `WebSocketUpgrade`.
+fn handshake_response_headers(mut headers: HeaderMap) -> HeaderMap {
+    remove_hop_by_hop_headers(&mut headers);
+    for name in [
+        header::SEC_WEBSOCKET_ACCEPT,
+        header::SEC_WEBSOCKET_PROTOCOL,
+        header::SEC_WEBSOCKET_EXTENSIONS,
+    ] {
+        headers.remove(name);
     }
+    headers
+}
+
+/// Passes the strangled service's answer to a handshake it didn't accept, e.g. a 401, on to the
+/// client.
+fn rejected_handshake_response(
+    rejection: hyper::Response<hyper::Body>,
+    upstream: Selected,
+) -> axum::response::Response {
+    let (mut parts, body) = rejection.into_parts();
+    remove_hop_by_hop_headers(&mut parts.headers);
+    axum::response::Response::from_parts(
+        parts,
+        axum::body::boxed(UpstreamBody::new(body, Some(upstream))),
+    )
 }
 
 trait Axumable {
@@ -69,7 +245,7 @@ impl Axumable for TungsteniteMessage {
                 });
                 AxumMessage::Close(close_message)
             }
-            TungsteniteMessage::Frame(_f) => todo!(),
+            TungsteniteMessage::Frame(f) => AxumMessage::Binary(f.into_data()),
         }
     }
 }
@@ -111,73 +287,383 @@ fn tungstenite_close_code_to_axum(
     code.into()
 }
 
+/// The client for the handshakes with the strangled service. It's separate from the http client,
+/// so `wss` can use the TLS implementation of the `websocket-*` features.
+#[derive(Clone)]
+pub(crate) enum WebSocketClient {
+    Plain(hyper::Client<HttpConnector>),
+    #[cfg(feature = "websocket-native-tls")]
+    NativeTls(hyper::Client<hyper_tls::HttpsConnector<HttpConnector>>),
+    #[cfg(all(
+        not(feature = "websocket-native-tls"),
+        any(
+            feature = "websocket-rustls-tls-native-roots",
+            feature = "websocket-rustls-tls-webpki-roots"
+        )
+    ))]
+    Rustls(hyper::Client<hyper_rustls::HttpsConnector<HttpConnector>>),
+}
+
+impl WebSocketClient {
+    pub(crate) fn new(scheme: &WebSocketScheme, http_connector: HttpConnector) -> Self {
+        match scheme {
+            WebSocketScheme::WS => {
+                WebSocketClient::Plain(hyper::Client::builder().build(http_connector))
+            }
+            #[cfg(any(
+                feature = "websocket-native-tls",
+                feature = "websocket-rustls-tls-native-roots",
+                feature = "websocket-rustls-tls-webpki-roots"
+            ))]
+            WebSocketScheme::WSS => WebSocketClient::tls(http_connector),
+        }
+    }
+
+    #[cfg(feature = "websocket-native-tls")]
+    fn tls(mut http_connector: HttpConnector) -> Self {
+        http_connector.enforce_http(false);
+        let https = hyper_tls::HttpsConnector::new_with_connector(http_connector);
+        WebSocketClient::NativeTls(hyper::Client::builder().build(https))
+    }
+
+    #[cfg(all(
+        not(feature = "websocket-native-tls"),
+        any(
+            feature = "websocket-rustls-tls-native-roots",
+            feature = "websocket-rustls-tls-webpki-roots"
+        )
+    ))]
+    fn tls(mut http_connector: HttpConnector) -> Self {
+        http_connector.enforce_http(false);
+        let https = hyper_rustls::HttpsConnectorBuilder::new();
+        #[cfg(feature = "websocket-rustls-tls-native-roots")]
+        let https = https.with_native_roots();
+        #[cfg(not(feature = "websocket-rustls-tls-native-roots"))]
+        let https = https.with_webpki_roots();
+        let https = https
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http_connector);
+        WebSocketClient::Rustls(hyper::Client::builder().build(https))
+    }
+
+    fn request(&self, req: hyper::Request<hyper::Body>) -> hyper::client::ResponseFuture {
+        match self {
+            WebSocketClient::Plain(client) => client.request(req),
+            #[cfg(feature = "websocket-native-tls")]
+            WebSocketClient::NativeTls(client) => client.request(req),
+            #[cfg(all(
+                not(feature = "websocket-native-tls"),
+                any(
+                    feature = "websocket-rustls-tls-native-roots",
+                    feature = "websocket-rustls-tls-webpki-roots"
+                )
+            ))]
+            WebSocketClient::Rustls(client) => client.request(req),
+        }
+    }
+}
+
+/// How long to wait for a peer to acknowledge a close frame before dropping the connection.
+const CLOSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The payload of the keepalive pings, so their pongs aren't passed on to the other side.
+const PING_PAYLOAD: &[u8] = b"axum-strangler";
+
+/// How websocket connections are proxied, see the `with_web_socket_*` methods of
+/// [`crate::StranglerBuilder`].
+#[derive(Clone, Default)]
+pub(crate) struct WebSocketSettings {
+    pub(crate) ping_interval: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) max_message_size: Option<usize>,
+    pub(crate) max_frame_size: Option<usize>,
+    pub(crate) message_router: Option<NewMessageRouter>,
+}
+
+/// Makes the [`WebSocketMessageRouter`] of a connection from its handshake request.
+pub(crate) type NewMessageRouter =
+    Arc<dyn Fn(&Parts) -> Box<dyn WebSocketMessageRouter> + Send + Sync>;
+
+impl WebSocketSettings {
+    /// The configuration of the connection with the strangled service.
+    fn config(&self) -> WebSocketConfig {
+        let default = WebSocketConfig::default();
+        WebSocketConfig {
+            max_message_size: self.max_message_size.or(default.max_message_size),
+            max_frame_size: self.max_frame_size.or(default.max_frame_size),
+            ..default
+        }
+    }
+}
+
 async fn on_websocket_upgrade(
     socket: WebSocket,
-    strangled_websocket: tokio_tungstenite::WebSocketStream<
-        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-    >,
+    strangled_websocket: WebSocketStream<hyper::upgrade::Upgraded>,
+    settings: WebSocketSettings,
+    message_router: Option<Box<dyn WebSocketMessageRouter>>,
 ) {
-    let (mut strangled_sink, mut strangled_stream) = strangled_websocket.split();
-    let (mut client_sink, mut client_stream) = socket.split();
-
-    let (sender1, mut receiver1) = tokio::sync::broadcast::channel::<()>(1);
-    let (sender2, mut receiver2) = tokio::sync::broadcast::channel::<()>(1);
-
-    let from_strangled_to_client_jh = tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                Some(Ok(msg)) = strangled_stream.next() => {
-                    match client_sink
-                        .send(msg.to_axum())
-                        .await {
-                            Ok(_) => {},
-                            Err(e) => {
-                                dbg!(e);
-                                sender2.send(()).unwrap();
-                                return;
-                            }
-                        }
-                }
-                _ = receiver1.recv() => {
-                    return;
-                }
-                else => {
-                    sender2.send(()).unwrap();
-                    return;
-                }
+    let client = socket
+        .map_ok(Tungsteniteable::to_tungstenite)
+        .map_err(axum_error_to_tungstenite)
+        .with(|msg: TungsteniteMessage| std::future::ready(Ok(msg.to_axum())))
+        .sink_map_err(axum_error_to_tungstenite);
+    proxy(
+        client,
+        strangled_websocket,
+        settings,
+        message_router,
+        CLOSE_HANDSHAKE_TIMEOUT,
+    )
+    .await;
+}
+
+fn axum_error_to_tungstenite(e: axum::Error) -> tungstenite::Error {
+    match e.into_inner().downcast::<tungstenite::Error>() {
+        Ok(e) => *e,
+        Err(e) => tungstenite::Error::Io(std::io::Error::other(e)),
+    }
+}
+
+/// Either side of a proxied websocket connection.
+trait Peer:
+    Stream<Item = Result<TungsteniteMessage, tungstenite::Error>>
+    + Sink<TungsteniteMessage, Error = tungstenite::Error>
+    + Unpin
+{
+}
+
+impl<T> Peer for T where
+    T: Stream<Item = Result<TungsteniteMessage, tungstenite::Error>>
+        + Sink<TungsteniteMessage, Error = tungstenite::Error>
+        + Unpin
+{
+}
+
+/// How a proxied websocket connection ended.
+#[derive(Debug)]
+enum Ending {
+    /// The side sent a close frame.
+    Closed(Option<CloseFrame<'static>>),
+    /// The side broke the protocol, or sent a message that's too big, both sides get a close
+    /// frame with the matching code.
+    Failed(CloseFrame<'static>),
+    /// The connection was lost without a close frame. The matching close code, 1006, can't be
+    /// sent in a close frame, so the connection with the other side is dropped as well.
+    Abnormal,
+    /// Neither side sent a message within the idle timeout, both sides get a close frame.
+    Idle,
+}
+
+impl From<tungstenite::Error> for Ending {
+    fn from(e: tungstenite::Error) -> Self {
+        let (code, reason) = match e {
+            tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake) => {
+                return Ending::Abnormal
             }
+            tungstenite::Error::Protocol(_) => (CloseCode::Protocol, "protocol error"),
+            tungstenite::Error::Utf8 => (CloseCode::Invalid, "invalid utf-8"),
+            tungstenite::Error::Capacity(_) => (CloseCode::Size, "message too big"),
+            _ => return Ending::Abnormal,
+        };
+        Ending::Failed(CloseFrame {
+            code,
+            reason: reason.into(),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Client,
+    Strangled,
+}
+
+impl Side {
+    fn other(self) -> Side {
+        match self {
+            Side::Client => Side::Strangled,
+            Side::Strangled => Side::Client,
         }
+    }
+}
+
+enum Event {
+    Received(Side, Option<Result<TungsteniteMessage, tungstenite::Error>>),
+    Ping,
+    Idle,
+}
+
+/// Passes messages between the client and the strangled service until either side ends the
+/// connection, then ends the connection with the other side in the same way.
+async fn proxy(
+    mut client: impl Peer,
+    mut strangled: impl Peer,
+    settings: WebSocketSettings,
+    mut message_router: Option<Box<dyn WebSocketMessageRouter>>,
+    close_timeout: Duration,
+) {
+    let mut ping = settings.ping_interval.map(|ping_interval| {
+        tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval)
     });
+    let mut idle = settings
+        .idle_timeout
+        .map(|idle_timeout| Box::pin(tokio::time::sleep(idle_timeout)));
 
-    let from_client_to_strangled_jh = tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                Some(Ok(msg)) = client_stream.next() => {
-                    match strangled_sink
-                        .send(msg.to_tungstenite())
-                        .await
-                        {
-                            Ok(_) => {},
-                            Err(e) => {
-                                dbg!(e);
-                                sender1.send(()).unwrap();
-                                return;
-                            }
-                        }
+    let (side, ending) = loop {
+        let event = tokio::select! {
+            next = client.next() => Event::Received(Side::Client, next),
+            next = strangled.next() => Event::Received(Side::Strangled, next),
+            _ = tick(&mut ping) => Event::Ping,
+            _ = elapsed(&mut idle) => Event::Idle,
+        };
+        let (side, msg) = match event {
+            Event::Received(side, Some(Ok(msg))) => (side, msg),
+            Event::Received(side, Some(Err(e))) => break (Some(side), e.into()),
+            Event::Received(side, None) => break (Some(side), Ending::Abnormal),
+            Event::Idle => break (None, Ending::Idle),
+            Event::Ping => {
+                let ping = || TungsteniteMessage::Ping(PING_PAYLOAD.to_vec());
+                if let Err(e) = client.send(ping()).await {
+                    break (Some(Side::Client), e.into());
                 }
-                _ = receiver2.recv() => {
-                    return;
+                if let Err(e) = strangled.send(ping()).await {
+                    break (Some(Side::Strangled), e.into());
                 }
-                else => {
-                    sender1.send(()).unwrap();
-                    return;
+                continue;
+            }
+        };
+
+        match msg {
+            TungsteniteMessage::Close(frame) => break (Some(side), Ending::Closed(frame)),
+            TungsteniteMessage::Pong(ref payload) if payload == PING_PAYLOAD => continue,
+            TungsteniteMessage::Ping(_) | TungsteniteMessage::Pong(_) => {}
+            _ => {
+                if let (Some(idle), Some(idle_timeout)) = (&mut idle, settings.idle_timeout) {
+                    idle.as_mut()
+                        .reset(tokio::time::Instant::now() + idle_timeout);
                 }
             }
         }
-    });
 
-    from_client_to_strangled_jh.await.unwrap();
-    from_strangled_to_client_jh.await.unwrap();
+        let (destination, msg) = match &mut message_router {
+            Some(message_router) if msg.is_text() || msg.is_binary() => {
+                match route(message_router.as_mut(), side, msg).await {
+                    Some(routed) => routed,
+                    None => continue,
+                }
+            }
+            _ => (side.other(), msg),
+        };
+        let sent = match destination {
+            Side::Client => client.send(msg).await.map_err(|e| (Side::Client, e)),
+            Side::Strangled => strangled.send(msg).await.map_err(|e| (Side::Strangled, e)),
+        };
+        if let Err((side, e)) = sent {
+            break (Some(side), e.into());
+        }
+    };
+
+    tracing::debug!(?ending, "websocket connection ended");
+    let finished = match side {
+        Some(Side::Client) => {
+            let finish = futures_util::future::join(
+                finish(&mut client, &ending),
+                pass_on(&mut strangled, &ending),
+            );
+            tokio::time::timeout(close_timeout, finish).await
+        }
+        Some(Side::Strangled) => {
+            let finish = futures_util::future::join(
+                finish(&mut strangled, &ending),
+                pass_on(&mut client, &ending),
+            );
+            tokio::time::timeout(close_timeout, finish).await
+        }
+        None => {
+            let finish = futures_util::future::join(
+                pass_on(&mut client, &ending),
+                pass_on(&mut strangled, &ending),
+            );
+            tokio::time::timeout(close_timeout, finish).await
+        }
+    };
+    if finished.is_err() {
+        tracing::debug!("websocket close handshake timed out");
+    }
+}
+
+/// Where a message of `from` should go according to the `message_router`, if anywhere.
+async fn route(
+    message_router: &mut dyn WebSocketMessageRouter,
+    from: Side,
+    msg: TungsteniteMessage,
+) -> Option<(Side, TungsteniteMessage)> {
+    let action = match from {
+        Side::Client => message_router.on_client_message(msg.to_axum()).await,
+        Side::Strangled => message_router.on_strangled_message(msg.to_axum()).await,
+    };
+    match action {
+        MessageAction::Forward(msg) => Some((from.other(), msg.to_tungstenite())),
+        MessageAction::Reply(msg) => Some((from, msg.to_tungstenite())),
+        MessageAction::Drop => None,
+    }
+}
+
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn elapsed(sleep: &mut Option<Pin<Box<tokio::time::Sleep>>>) {
+    match sleep {
+        Some(sleep) => sleep.await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Completes the close handshake with the side that ended the connection.
+async fn finish(peer: &mut impl Peer, ending: &Ending) {
+    match ending {
+        // tungstenite already queued the reply to the close frame, this sends it.
+        Ending::Closed(_) => {
+            peer.close().await.ok();
+        }
+        // Whatever the peer sends after the failure can't be trusted, so there's no point in
+        // waiting for it to acknowledge the close frame.
+        Ending::Failed(frame) => {
+            peer.send(TungsteniteMessage::Close(Some(frame.clone())))
+                .await
+                .ok();
+        }
+        Ending::Idle => pass_on(peer, ending).await,
+        Ending::Abnormal => {}
+    }
+}
+
+/// Sends a close frame matching `ending`, and waits for the peer to acknowledge it.
+async fn pass_on(peer: &mut impl Peer, ending: &Ending) {
+    let frame = match ending {
+        Ending::Closed(frame) => frame.clone(),
+        Ending::Failed(frame) => Some(frame.clone()),
+        Ending::Idle => Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "idle timeout".into(),
+        }),
+        Ending::Abnormal => return,
+    };
+    if peer.send(TungsteniteMessage::Close(frame)).await.is_err() {
+        return;
+    }
+    while let Some(Ok(msg)) = peer.next().await {
+        if msg.is_close() {
+            return;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -303,4 +789,466 @@ mod tests {
         stranglee_joinhandle.await.unwrap();
         strangler_joinhandle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn passes_on_refused_upgrades() {
+        let router = Router::new().route(
+            "/api/websocket",
+            get(
+                |Extension(StopChannel(tx_arc)): Extension<StopChannel>| async move {
+                    tx_arc.send(()).unwrap();
+                    (
+                        axum::http::StatusCode::UNAUTHORIZED,
+                        [(axum::http::header::WWW_AUTHENTICATE, "Bearer")],
+                        "no session",
+                    )
+                },
+            ),
+        );
+
+        let StartupHelper {
+            strangler_port,
+            strangler_joinhandle,
+            stranglee_joinhandle,
+        } = start_up_strangler_and_strangled(router).await;
+
+        // tungstenite doesn't read the body of a refused handshake, so use a plain http client.
+        let refused = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}/api/websocket", strangler_port))
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(refused.status(), axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(refused.headers()["www-authenticate"], "Bearer");
+        assert_eq!(refused.text().await.unwrap(), "no session");
+
+        stranglee_joinhandle.await.unwrap();
+        strangler_joinhandle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable_strangled_service_is_a_bad_gateway() {
+        let strangled_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let strangler_svc = Strangler::new(
+            axum::http::uri::Authority::try_from(format!("127.0.0.1:{}", strangled_port)).unwrap(),
+        );
+        let strangler_tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let strangler_port = strangler_tcp.local_addr().unwrap().port();
+        tokio::spawn(
+            axum::Server::from_tcp(strangler_tcp)
+                .unwrap()
+                .serve(Router::new().fallback(strangler_svc).into_make_service()),
+        );
+
+        let refused = tokio_tungstenite::connect_async(format!(
+            "ws://127.0.0.1:{}/api/websocket",
+            strangler_port
+        ))
+        .await;
+        match refused {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+            }
+            other => panic!("expected a bad gateway, got {:?}", other),
+        }
+    }
+
+    #[cfg(any(
+        feature = "websocket-native-tls",
+        feature = "websocket-rustls-tls-native-roots",
+        feature = "websocket-rustls-tls-webpki-roots"
+    ))]
+    #[tokio::test]
+    async fn failed_tls_handshakes_are_tls_errors() {
+        // A plain http server, so the TLS handshake fails.
+        let mock_server = wiremock::MockServer::start().await;
+        let strangler_svc = Strangler::builder(
+            axum::http::uri::Authority::try_from(mock_server.address().to_string()).unwrap(),
+        )
+        .with_web_socket_scheme(WebSocketScheme::WSS)
+        .with_error_renderer(|e| {
+            axum::response::IntoResponse::into_response((
+                e.status_code(),
+                format!("tls: {}", matches!(e, StranglerError::Tls(_))),
+            ))
+        })
+        .build();
+        let strangler_tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let strangler_port = strangler_tcp.local_addr().unwrap().port();
+        tokio::spawn(
+            axum::Server::from_tcp(strangler_tcp)
+                .unwrap()
+                .serve(Router::new().fallback(strangler_svc).into_make_service()),
+        );
+
+        let response = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}/api/websocket", strangler_port))
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+        assert_eq!(response.text().await.unwrap(), "tls: true");
+    }
+
+    #[tokio::test]
+    async fn forwards_handshake_headers_and_subprotocol() {
+        let router = Router::new().route(
+            "/api/websocket",
+            get(
+                |ws: axum::extract::ws::WebSocketUpgrade,
+                 headers: axum::http::HeaderMap,
+                 Extension(StopChannel(tx_arc)): Extension<StopChannel>| async move {
+                    let cookie = headers[axum::http::header::COOKIE]
+                        .to_str()
+                        .unwrap()
+                        .to_owned();
+                    (
+                        [(axum::http::header::SET_COOKIE, "session=2")],
+                        ws.protocols(["chat"]).on_upgrade(|mut socket| async move {
+                            socket
+                                .send(axum::extract::ws::Message::Text(cookie))
+                                .await
+                                .ok();
+                            tx_arc.send(()).unwrap();
+                        }),
+                    )
+                },
+            ),
+        );
+
+        let StartupHelper {
+            strangler_port,
+            strangler_joinhandle,
+            stranglee_joinhandle,
+        } = start_up_strangler_and_strangled(router).await;
+
+        let mut request =
+            tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(
+                format!("ws://127.0.0.1:{}/api/websocket", strangler_port),
+            )
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("cookie", "session=1".parse().unwrap());
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", "other, chat".parse().unwrap());
+        let (mut ws_connection, response) =
+            tokio_tungstenite::connect_async(request).await.unwrap();
+
+        assert_eq!(response.headers()["sec-websocket-protocol"], "chat");
+        assert_eq!(response.headers()["set-cookie"], "session=2");
+        assert_eq!(
+            ws_connection.next().await.unwrap().unwrap(),
+            tokio_tungstenite::tungstenite::Message::Text("session=1".to_owned())
+        );
+
+        stranglee_joinhandle.await.unwrap();
+        strangler_joinhandle.await.unwrap();
+    }
+
+    mod proxy {
+        use std::time::Duration;
+
+        use futures_util::{SinkExt, StreamExt};
+        use tokio::{
+            io::{AsyncWriteExt, DuplexStream},
+            task::JoinHandle,
+        };
+        use tokio_tungstenite::{
+            tungstenite::{
+                error::ProtocolError,
+                protocol::{CloseFrame, Role},
+                Error, Message,
+            },
+            WebSocketStream,
+        };
+
+        use crate::inner::WebSocketSettings;
+
+        /// A client connected to the proxy, and the raw connection of the proxy with the strangled
+        /// service.
+        async fn proxied_with(
+            settings: WebSocketSettings,
+            close_timeout: Duration,
+        ) -> (WebSocketStream<DuplexStream>, DuplexStream, JoinHandle<()>) {
+            let (client, proxy_client) = tokio::io::duplex(4096);
+            let (proxy_strangled, strangled) = tokio::io::duplex(4096);
+            let config = Some(settings.config());
+            let (parts, _) = axum::http::Request::get("/chat")
+                .body(())
+                .unwrap()
+                .into_parts();
+            let message_router = settings
+                .message_router
+                .as_ref()
+                .map(|new_message_router| new_message_router(&parts));
+            let proxy = tokio::spawn(crate::inner::websocket::proxy(
+                WebSocketStream::from_raw_socket(proxy_client, Role::Server, config).await,
+                WebSocketStream::from_raw_socket(proxy_strangled, Role::Client, config).await,
+                settings,
+                message_router,
+                close_timeout,
+            ));
+            let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+            (client, strangled, proxy)
+        }
+
+        async fn proxied(
+            close_timeout: Duration,
+        ) -> (WebSocketStream<DuplexStream>, DuplexStream, JoinHandle<()>) {
+            proxied_with(WebSocketSettings::default(), close_timeout).await
+        }
+
+        async fn server(strangled: DuplexStream) -> WebSocketStream<DuplexStream> {
+            WebSocketStream::from_raw_socket(strangled, Role::Server, None).await
+        }
+
+        fn close(code: u16, reason: &'static str) -> Message {
+            Message::Close(Some(CloseFrame {
+                code: code.into(),
+                reason: reason.into(),
+            }))
+        }
+
+        async fn finished(proxy: JoinHandle<()>) {
+            tokio::time::timeout(Duration::from_secs(1), proxy)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        #[tokio::test]
+        async fn passes_on_client_close() {
+            let (mut client, strangled, proxy) = proxied(Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            client.send(close(4000, "bye")).await.unwrap();
+            assert_eq!(strangled.next().await.unwrap().unwrap(), close(4000, "bye"));
+            assert!(strangled.next().await.is_none());
+            assert_eq!(client.next().await.unwrap().unwrap(), close(4000, "bye"));
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn passes_on_strangled_close() {
+            let (mut client, strangled, proxy) = proxied(Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            strangled.send(close(1001, "going away")).await.unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                close(1001, "going away")
+            );
+            assert!(client.next().await.is_none());
+            assert_eq!(
+                strangled.next().await.unwrap().unwrap(),
+                close(1001, "going away")
+            );
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn drops_client_when_strangled_connection_is_lost() {
+            let (mut client, strangled, proxy) = proxied(Duration::from_secs(1)).await;
+
+            drop(strangled);
+            assert!(matches!(
+                client.next().await,
+                Some(Err(Error::Protocol(
+                    ProtocolError::ResetWithoutClosingHandshake
+                )))
+            ));
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn closes_client_on_strangled_protocol_error() {
+            let (mut client, mut strangled, proxy) = proxied(Duration::from_millis(50)).await;
+
+            // A binary frame with the reserved bits set.
+            strangled.write_all(&[0xf2, 0x00]).await.unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                close(1002, "protocol error")
+            );
+            assert!(client.next().await.is_none());
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn gives_up_on_unacknowledged_close() {
+            let (mut client, strangled, proxy) = proxied(Duration::from_millis(50)).await;
+            let _strangled = server(strangled).await;
+
+            client.send(close(1000, "")).await.unwrap();
+            assert_eq!(client.next().await.unwrap().unwrap(), close(1000, ""));
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn closes_with_message_too_big() {
+            let settings = WebSocketSettings {
+                max_message_size: Some(16),
+                ..WebSocketSettings::default()
+            };
+            let (mut client, strangled, proxy) =
+                proxied_with(settings, Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            client.send(Message::Text("a".repeat(32))).await.unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                close(1009, "message too big")
+            );
+            assert_eq!(
+                strangled.next().await.unwrap().unwrap(),
+                close(1009, "message too big")
+            );
+            assert!(strangled.next().await.is_none());
+            assert!(client.next().await.is_none());
+            finished(proxy).await;
+        }
+
+        #[tokio::test]
+        async fn pings_both_sides() {
+            let settings = WebSocketSettings {
+                ping_interval: Some(Duration::from_millis(10)),
+                ..WebSocketSettings::default()
+            };
+            let (mut client, strangled, _proxy) =
+                proxied_with(settings, Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            assert!(client.next().await.unwrap().unwrap().is_ping());
+            assert!(strangled.next().await.unwrap().unwrap().is_ping());
+            // The pongs aren't passed on.
+            client.send(Message::Text("hi".to_owned())).await.unwrap();
+            let mut received = strangled.next().await.unwrap().unwrap();
+            while received.is_ping() {
+                received = strangled.next().await.unwrap().unwrap();
+            }
+            assert_eq!(received, Message::Text("hi".to_owned()));
+        }
+
+        #[tokio::test]
+        async fn closes_idle_connections() {
+            let settings = WebSocketSettings {
+                idle_timeout: Some(Duration::from_millis(50)),
+                ..WebSocketSettings::default()
+            };
+            let (mut client, strangled, proxy) =
+                proxied_with(settings, Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            let (client_close, strangled_close) =
+                futures_util::future::join(client.next(), strangled.next()).await;
+            assert_eq!(client_close.unwrap().unwrap(), close(1001, "idle timeout"));
+            assert_eq!(
+                strangled_close.unwrap().unwrap(),
+                close(1001, "idle timeout")
+            );
+            futures_util::future::join(client.next(), strangled.next()).await;
+            finished(proxy).await;
+        }
+
+        struct Envelopes {
+            path: String,
+            client_messages: usize,
+        }
+
+        #[axum::async_trait]
+        impl crate::WebSocketMessageRouter for Envelopes {
+            async fn on_client_message(
+                &mut self,
+                message: axum::extract::ws::Message,
+            ) -> crate::MessageAction {
+                self.client_messages += 1;
+                match message {
+                    axum::extract::ws::Message::Text(text) if text == "new" => {
+                        crate::MessageAction::Reply(axum::extract::ws::Message::Text(format!(
+                            "handled on {}",
+                            self.path
+                        )))
+                    }
+                    axum::extract::ws::Message::Text(text) => {
+                        crate::MessageAction::Forward(axum::extract::ws::Message::Text(format!(
+                            "{} {}",
+                            self.client_messages,
+                            text.to_uppercase()
+                        )))
+                    }
+                    message => crate::MessageAction::Forward(message),
+                }
+            }
+
+            async fn on_strangled_message(
+                &mut self,
+                message: axum::extract::ws::Message,
+            ) -> crate::MessageAction {
+                match message {
+                    axum::extract::ws::Message::Text(text) if text == "internal" => {
+                        crate::MessageAction::Drop
+                    }
+                    message => crate::MessageAction::Forward(message),
+                }
+            }
+        }
+
+        #[tokio::test]
+        async fn routes_messages() {
+            let settings = WebSocketSettings {
+                message_router: Some(std::sync::Arc::new(|parts: &axum::http::request::Parts| {
+                    Box::new(Envelopes {
+                        path: parts.uri.path().to_owned(),
+                        client_messages: 0,
+                    }) as Box<dyn crate::WebSocketMessageRouter>
+                })),
+                ..WebSocketSettings::default()
+            };
+            let (mut client, strangled, _proxy) =
+                proxied_with(settings, Duration::from_secs(1)).await;
+            let mut strangled = server(strangled).await;
+
+            client.send(Message::Text("new".to_owned())).await.unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                Message::Text("handled on /chat".to_owned())
+            );
+
+            client
+                .send(Message::Text("legacy".to_owned()))
+                .await
+                .unwrap();
+            assert_eq!(
+                strangled.next().await.unwrap().unwrap(),
+                Message::Text("2 LEGACY".to_owned())
+            );
+
+            strangled
+                .send(Message::Text("internal".to_owned()))
+                .await
+                .unwrap();
+            strangled
+                .send(Message::Text("push".to_owned()))
+                .await
+                .unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                Message::Text("push".to_owned())
+            );
+        }
+    }
 }
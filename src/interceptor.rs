@@ -0,0 +1,118 @@
+use axum::{body::Body, http::Request, response::Response};
+
+/// Tweaks the traffic going to and coming from the strangled service, e.g. to add an internal
+/// auth header or drop a cookie.
+/// Interceptors see requests in the order they were added to the `StranglerBuilder`, and
+/// responses in reverse order, like middleware wrapped around each other.
+/// Errors from talking to the strangled service don't pass through `on_response`.
+/// ```rust
+/// use axum_strangler::StranglerInterceptor;
+///
+/// struct InternalAuth;
+///
+/// #[axum::async_trait]
+/// impl StranglerInterceptor for InternalAuth {
+///     async fn on_request(&self, req: &mut axum::http::Request<axum::body::Body>) {
+///         req.headers_mut().insert(
+///             "x-internal-auth",
+///             axum::http::HeaderValue::from_static("secret"),
+///         );
+///     }
+/// }
+///
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_interceptor(InternalAuth)
+/// .build();
+/// ```
+#[axum::async_trait]
+pub trait StranglerInterceptor: Send + Sync {
+    /// Called before the request is forwarded.
+    async fn on_request(&self, _req: &mut Request<Body>) {}
+
+    /// Called with the response of the strangled service, before it's returned to the client.
+    async fn on_response(&self, _res: &mut Response) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use wiremock::{matchers::header, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::Strangler;
+
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[axum::async_trait]
+    impl StranglerInterceptor for Recorder {
+        async fn on_request(&self, req: &mut Request<Body>) {
+            let tenant = *req.extensions().get::<&'static str>().unwrap();
+            req.headers_mut()
+                .insert("x-tenant", axum::http::HeaderValue::from_static(tenant));
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} request", self.name));
+        }
+
+        async fn on_response(&self, res: &mut Response) {
+            res.headers_mut().insert(
+                "x-intercepted-by",
+                axum::http::HeaderValue::from_static(self.name),
+            );
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} response", self.name));
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_wrap_the_forwarded_call() {
+        let mock_server = MockServer::start().await;
+        Mock::given(header("x-tenant", "acme"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let strangler = Strangler::builder(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        )
+        .with_interceptor(Recorder {
+            name: "outer",
+            calls: calls.clone(),
+        })
+        .with_interceptor(Recorder {
+            name: "inner",
+            calls: calls.clone(),
+        })
+        .build();
+
+        let mut req = Request::get("/").body(Body::empty()).unwrap();
+        req.extensions_mut().insert("acme");
+        let response = strangler.forward_to_strangled(req).await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()["x-intercepted-by"], "outer");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "outer request",
+                "inner request",
+                "inner response",
+                "outer response"
+            ]
+        );
+    }
+}
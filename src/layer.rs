@@ -0,0 +1,544 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::ConnectInfo,
+    http::{header, request::Parts, Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    inner::body::{buffer_body, clone_request, Buffered},
+    StrangleFallthrough, Strangler,
+};
+
+type Predicate = Arc<dyn Fn(&Response) -> bool + Send + Sync>;
+type RequestPredicate = Arc<dyn Fn(&Parts) -> bool + Send + Sync>;
+
+/// Wraps the new service, and forwards requests to the strangled service when the new service
+/// doesn't handle them, i.e. responds with `404 Not Found` or `405 Method Not Allowed`, or
+/// returns [`StrangleFallthrough`].
+///
+/// The strangled service doesn't have to be a bare [`Strangler`], it can be any service, e.g. a
+/// `Strangler` with some tower middleware that should only apply to forwarded requests.
+///
+/// To be able to send the request to both services, its body is kept in memory, up to 64 KiB by
+/// default. Requests with larger bodies can't fall through, when the new service doesn't handle
+/// them the client gets a `413 Payload Too Large`, unless
+/// [`StranglerLayer::with_large_body_predicate`] sends them to the strangled service right away.
+/// The new service gets the request with all its extensions, a request that falls through only
+/// keeps `ConnectInfo<SocketAddr>`. Requests that upgrade the connection, like websockets, can't
+/// fall through, as only one service can take over the connection, they go to the new service
+/// unless [`StranglerLayer::with_upgrade_predicate`] sends them to the strangled service.
+/// ```rust
+/// use axum::routing::get;
+/// use axum_strangler::{Strangler, StranglerLayer};
+///
+/// let strangler = Strangler::new(axum::http::uri::Authority::from_static("127.0.0.1:3333"));
+/// let app = axum::Router::new()
+///     .route("/new", get(|| async { "Hello from the new service" }))
+///     .layer(StranglerLayer::new(strangler));
+/// ```
+#[derive(Clone)]
+pub struct StranglerLayer<F = Strangler> {
+    strangled: F,
+    should_fall_through: Predicate,
+    max_body_size: usize,
+    strangle_large_body: RequestPredicate,
+    strangle_upgrade: RequestPredicate,
+}
+
+impl<F> StranglerLayer<F> {
+    pub fn new(strangled: F) -> Self {
+        Self {
+            strangled,
+            should_fall_through: Arc::new(|response: &Response| {
+                matches!(
+                    response.status(),
+                    StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                )
+            }),
+            max_body_size: 64 * 1024,
+            strangle_large_body: Arc::new(|_: &Parts| false),
+            strangle_upgrade: Arc::new(|_: &Parts| false),
+        }
+    }
+
+    /// Decides which responses of the new service result in the request being forwarded to the
+    /// strangled service, replacing the default of `404 Not Found` and `405 Method Not Allowed`.
+    /// Responses from [`StrangleFallthrough`] are always forwarded.
+    pub fn with_fall_through_predicate<P>(self, should_fall_through: P) -> Self
+    where
+        P: Fn(&Response) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            should_fall_through: Arc::new(should_fall_through),
+            ..self
+        }
+    }
+
+    /// The largest request body, in bytes, that is kept in memory so the request can still fall
+    /// through to the strangled service.
+    pub fn with_max_body_size(self, max_body_size: usize) -> Self {
+        Self {
+            max_body_size,
+            ..self
+        }
+    }
+
+    /// Decides which requests with a body larger than the max body size are sent straight to the
+    /// strangled service, instead of to the new service.
+    pub fn with_large_body_predicate<P>(self, strangle_large_body: P) -> Self
+    where
+        P: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            strangle_large_body: Arc::new(strangle_large_body),
+            ..self
+        }
+    }
+
+    /// Decides which requests that upgrade the connection, like websocket handshakes, are sent
+    /// straight to the strangled service, instead of to the new service.
+    pub fn with_upgrade_predicate<P>(self, strangle_upgrade: P) -> Self
+    where
+        P: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            strangle_upgrade: Arc::new(strangle_upgrade),
+            ..self
+        }
+    }
+}
+
+impl<F> StranglerLayer<F> {
+    fn falls_through(&self, response: &Response) -> bool {
+        StrangleFallthrough::is_set(response) || (self.should_fall_through)(response)
+    }
+}
+
+impl<S, F: Clone> Layer<S> for StranglerLayer<F> {
+    type Service = StranglerMiddleware<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        StranglerMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service created by a [`StranglerLayer`].
+#[derive(Clone)]
+pub struct StranglerMiddleware<S, F = Strangler> {
+    inner: S,
+    layer: StranglerLayer<F>,
+}
+
+impl<S, F, ResBody, StrangledResBody> Service<Request<Body>> for StranglerMiddleware<S, F>
+where
+    S: Service<Request<Body>, Response = axum::http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    F: Service<Request<Body>, Response = axum::http::Response<StrangledResBody>>
+        + Clone
+        + Send
+        + 'static,
+    F::Error: Into<S::Error>,
+    F::Future: Send,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+    StrangledResBody: HttpBody<Data = Bytes> + Send + 'static,
+    StrangledResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // Use the service that was driven to readiness, leave a fresh clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        let fut = async move {
+            let (parts, body) = req.into_parts();
+            if parts.headers.contains_key(header::UPGRADE) {
+                let strangle = (layer.strangle_upgrade)(&parts);
+                let req = Request::from_parts(parts, body);
+                if strangle {
+                    return call_strangled(layer.strangled, req).await;
+                }
+                return Ok(inner.call(req).await?.map(axum::body::boxed));
+            }
+
+            let body = match buffer_body(body, layer.max_body_size).await {
+                Ok(Buffered::Complete(body)) => body,
+                Ok(Buffered::TooLarge(body)) => {
+                    let strangle = (layer.strangle_large_body)(&parts);
+                    let req = Request::from_parts(parts, body);
+                    if strangle {
+                        tracing::debug!(
+                            "request body is too large to fall through, forwarding it right away"
+                        );
+                        return call_strangled(layer.strangled, req).await;
+                    }
+
+                    tracing::debug!("request body is too large to fall through, not buffering");
+                    let response = inner.call(req).await?.map(axum::body::boxed);
+                    if layer.falls_through(&response) {
+                        return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+                    }
+                    return Ok(response);
+                }
+                Err(e) => {
+                    tracing::debug!(error = %e, "could not read the request body");
+                    return Ok(e.into_response());
+                }
+            };
+
+            let mut strangled_req = clone_request(&parts, body.clone());
+            if let Some(connect_info) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+                strangled_req.extensions_mut().insert(*connect_info);
+            }
+
+            let response = inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await?
+                .map(axum::body::boxed);
+            if !layer.falls_through(&response) {
+                return Ok(response);
+            }
+            call_strangled(layer.strangled, strangled_req).await
+        };
+        Box::pin(fut)
+    }
+}
+
+async fn call_strangled<F, ResBody, E>(mut strangled: F, req: Request<Body>) -> Result<Response, E>
+where
+    F: Service<Request<Body>, Response = axum::http::Response<ResBody>>,
+    F::Error: Into<E>,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    std::future::poll_fn(|cx| strangled.poll_ready(cx))
+        .await
+        .map_err(Into::into)?;
+    let response = strangled.call(req).await.map_err(Into::into)?;
+    Ok(response.map(axum::body::boxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use wiremock::{
+        matchers::{body_string, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn app(mock_server: &MockServer) -> axum::Router {
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        axum::Router::new()
+            .route("/new", get(|| async { "new" }))
+            .layer(StranglerLayer::new(strangler))
+    }
+
+    async fn call(app: &axum::Router, req: Request<Body>) -> (StatusCode, Bytes) {
+        let response = app.clone().call(req).await.unwrap();
+        let status = response.status();
+        (status, hyper::body::to_bytes(response).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn falls_through_when_not_handled() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/new"))
+            .and(body_string("payload"))
+            .respond_with(ResponseTemplate::new(201).set_body_string("legacy post"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/old"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("legacy"))
+            .mount(&mock_server)
+            .await;
+        let app = app(&mock_server);
+
+        let (status, body) = call(&app, Request::get("/new").body(Body::empty()).unwrap()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"new"[..]));
+
+        let (status, body) = call(&app, Request::get("/old").body(Body::empty()).unwrap()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"legacy"[..]));
+
+        let (status, body) = call(
+            &app,
+            Request::post("/new").body(Body::from("payload")).unwrap(),
+        )
+        .await;
+        assert_eq!(
+            (status, &body[..]),
+            (StatusCode::CREATED, &b"legacy post"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_through_on_predicate() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/teapot"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let app = axum::Router::new()
+            .route("/teapot", get(|| async { StatusCode::IM_A_TEAPOT }))
+            .layer(
+                StranglerLayer::new(strangler).with_fall_through_predicate(|response| {
+                    response.status() == StatusCode::IM_A_TEAPOT
+                }),
+            );
+
+        let (status, _) = call(&app, Request::get("/teapot").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&app, Request::get("/old").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn handlers_can_fall_through_explicitly() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/orders"))
+            .and(body_string("tenant=legacy"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("legacy"))
+            .mount(&mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let app = axum::Router::new()
+            .route(
+                "/orders",
+                axum::routing::post(|body: String| async move {
+                    if body == "tenant=new" {
+                        Ok("new")
+                    } else {
+                        Err(StrangleFallthrough)
+                    }
+                }),
+            )
+            .layer(StranglerLayer::new(strangler).with_fall_through_predicate(|_| false));
+
+        let (status, body) = call(
+            &app,
+            Request::post("/orders")
+                .body(Body::from("tenant=new"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"new"[..]));
+
+        let (status, body) = call(
+            &app,
+            Request::post("/orders")
+                .body(Body::from("tenant=legacy"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"legacy"[..]));
+    }
+
+    #[tokio::test]
+    async fn unhandled_large_bodies_are_too_large() {
+        let mock_server = MockServer::start().await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let app = axum::Router::new()
+            .route("/new", axum::routing::post(|body: String| async { body }))
+            .layer(StranglerLayer::new(strangler).with_max_body_size(3));
+
+        let (status, body) = call(
+            &app,
+            Request::post("/new").body(Body::from("payload")).unwrap(),
+        )
+        .await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"payload"[..]));
+
+        let (status, _) = call(
+            &app,
+            Request::post("/old").body(Body::from("payload")).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(mock_server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_bodies_go_to_the_strangled_service_on_predicate() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/upload"))
+            .and(body_string("payload"))
+            .respond_with(ResponseTemplate::new(201))
+            .mount(&mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let app = axum::Router::new().layer(
+            StranglerLayer::new(strangler)
+                .with_max_body_size(3)
+                .with_large_body_predicate(|parts| parts.uri.path() == "/upload"),
+        );
+
+        let (status, _) = call(
+            &app,
+            Request::post("/upload")
+                .body(Body::from("payload"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn new_service_keeps_the_request_extensions() {
+        let mock_server = MockServer::start().await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let app = axum::Router::new()
+            .route(
+                "/state",
+                get(
+                    |axum::Extension(state): axum::Extension<&'static str>,
+                     matched: axum::extract::MatchedPath| async move {
+                        format!("{} {}", state, matched.as_str())
+                    },
+                ),
+            )
+            .layer(StranglerLayer::new(strangler))
+            .layer(axum::Extension("state"));
+
+        let (status, body) = call(&app, Request::get("/state").body(Body::empty()).unwrap()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"state /state"[..]));
+    }
+
+    #[cfg(feature = "websocket")]
+    #[tokio::test]
+    async fn websocket_upgrades_go_to_one_service() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let stranglee_tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(stranglee_tcp.local_addr().unwrap().to_string())
+                .unwrap(),
+        );
+        let stranglee = axum::Router::new().route(
+            "/echo",
+            get(|ws: axum::extract::ws::WebSocketUpgrade| async {
+                ws.on_upgrade(|mut socket| async move {
+                    while let Some(Ok(message)) = socket.recv().await {
+                        if socket.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                })
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(stranglee_tcp)
+                .unwrap()
+                .serve(stranglee.into_make_service()),
+        );
+
+        let strangler_tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let strangler_address = strangler_tcp.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route(
+                "/new",
+                get(|ws: axum::extract::ws::WebSocketUpgrade| async {
+                    ws.on_upgrade(|mut socket| async move {
+                        let _ = socket
+                            .send(axum::extract::ws::Message::Text("new".to_owned()))
+                            .await;
+                    })
+                }),
+            )
+            .layer(
+                StranglerLayer::new(strangler)
+                    .with_upgrade_predicate(|parts| parts.uri.path() == "/echo"),
+            );
+        tokio::spawn(
+            axum::Server::from_tcp(strangler_tcp)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let (mut ws_connection, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/echo", strangler_address))
+                .await
+                .unwrap();
+        ws_connection
+            .send(Message::Text("hello".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            ws_connection.next().await.unwrap().unwrap(),
+            Message::Text("hello".to_owned())
+        );
+
+        let (mut ws_connection, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/new", strangler_address))
+                .await
+                .unwrap();
+        assert_eq!(
+            ws_connection.next().await.unwrap().unwrap(),
+            Message::Text("new".to_owned())
+        );
+    }
+}
@@ -15,7 +15,51 @@ use std::{
 use tower_service::Service;
 
 mod builder;
+mod canary;
+mod circuit_breaker;
+mod compare;
+mod decider;
+mod error;
+mod fallthrough;
+mod forwarded;
+mod health;
+#[cfg(feature = "health-check")]
+mod health_check;
 mod inner;
+mod interceptor;
+mod layer;
+#[cfg(feature = "websocket")]
+mod message_router;
+mod registry;
+mod retry;
+mod rewrite;
+mod router;
+mod shadow;
+mod switch;
+mod upstream;
+
+pub use builder::StranglerBuilder;
+pub use canary::{Canary, Implementation};
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+pub use compare::{CapturedResponse, Difference, JsonComparator, ResponseComparator};
+pub use decider::{FlagDecider, RoutingDecider, StaticDecider};
+pub use error::{InvalidBaseUri, StranglerError};
+pub use fallthrough::StrangleFallthrough;
+pub use forwarded::ForwardedHeaders;
+pub use health::StranglerHealth;
+#[cfg(feature = "health-check")]
+pub use health_check::HealthCheck;
+pub use interceptor::StranglerInterceptor;
+pub use layer::{StranglerLayer, StranglerMiddleware};
+#[cfg(feature = "websocket")]
+pub use message_router::{MessageAction, WebSocketMessageRouter};
+pub use registry::{RegisteredRoute, RouteRegistry, RouteState, UnknownRouteState};
+pub use retry::RetryPolicy;
+pub use rewrite::RewriteRule;
+pub use router::{StranglerRoute, StranglerRouter};
+pub use shadow::Shadow;
+pub use switch::RouteSwitch;
+pub use upstream::{HashKey, LoadBalancing, OutlierEjection, UpstreamHealth};
 
 pub enum HttpScheme {
     HTTP,
@@ -54,6 +98,7 @@ pub enum WebSocketScheme {
 #[derive(Clone)]
 pub struct Strangler {
     inner: Arc<dyn inner::InnerStrangler + Send + Sync>,
+    error_renderer: error::ErrorRenderer,
 }
 
 impl Strangler {
@@ -62,16 +107,49 @@ impl Strangler {
         Strangler::builder(strangled_authority).build()
     }
 
-    pub fn builder(strangled_authority: axum::http::uri::Authority) -> builder::StranglerBuilder {
-        builder::StranglerBuilder::new(strangled_authority)
+    pub fn builder(strangled_authority: axum::http::uri::Authority) -> StranglerBuilder {
+        StranglerBuilder::new(strangled_authority)
+    }
+
+    /// Creates a new `Strangler` for a base uri like `https://legacy.internal/app/`, see
+    /// [`StranglerBuilder::from_base_uri`].
+    pub fn from_base_uri(base_uri: axum::http::Uri) -> Result<Self, InvalidBaseUri> {
+        Ok(StranglerBuilder::from_base_uri(base_uri)?.build())
+    }
+
+    /// Like [`Strangler::builder`], for when the strangled service runs as several instances.
+    pub fn builder_for_pool(
+        strangled_authorities: impl IntoIterator<Item = axum::http::uri::Authority>,
+    ) -> StranglerBuilder {
+        StranglerBuilder::new_pool(strangled_authorities)
     }
 
     /// Forwards the request to the strangled service.
-    ///
+    /// If the strangled service can't be reached, the error is rendered into a response, see
+    /// [`StranglerBuilder::with_error_renderer`].
     pub async fn forward_to_strangled(
         &self,
         req: axum::http::Request<axum::body::Body>,
     ) -> axum::response::Response {
+        match self.try_forward_to_strangled(req).await {
+            Ok(response) => response,
+            Err(e) => (self.error_renderer)(e),
+        }
+    }
+
+    /// How the strangled service is doing, e.g. to report on in a readiness endpoint.
+    pub fn health(&self) -> StranglerHealth {
+        self.inner.health()
+    }
+
+    /// Forwards the request to the strangled service, but returns an error if the strangled
+    /// service couldn't be reached instead of rendering it into a response.
+    /// This allows telling apart a failing strangled service (e.g. a `500` it returned itself)
+    /// from not being able to talk to it at all.
+    pub async fn try_forward_to_strangled(
+        &self,
+        req: axum::http::Request<axum::body::Body>,
+    ) -> Result<axum::response::Response, StranglerError> {
         self.inner.forward_call_to_strangled(req).await
     }
 }
@@ -86,9 +164,9 @@ impl Service<axum::http::Request<axum::body::Body>> for Strangler {
     }
 
     fn call(&mut self, req: axum::http::Request<axum::body::Body>) -> Self::Future {
-        let inner = self.inner.clone();
+        let this = self.clone();
 
-        let fut = async move { Ok(inner.forward_call_to_strangled(req).await) };
+        let fut = async move { Ok(this.forward_to_strangled(req).await) };
         Box::pin(fut)
     }
 }
@@ -209,4 +287,272 @@ mod tests {
         stranglee_joinhandle.await.unwrap();
         strangler_joinhandle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn renders_errors_with_the_configured_renderer() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        drop(listener);
+
+        let strangler = Strangler::builder(authority)
+            .with_error_renderer(|e| {
+                axum::response::IntoResponse::into_response((
+                    e.status_code(),
+                    [("content-type", "application/problem+json")],
+                    "{}",
+                ))
+            })
+            .build();
+
+        let response = strangler
+            .forward_to_strangled(
+                axum::http::Request::get("/api/something")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await;
+
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/problem+json"
+        );
+    }
+
+    #[tokio::test]
+    async fn joins_base_path_after_rewrites() {
+        let mock_server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/app/users"))
+            .respond_with(wiremock::ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let base_uri = axum::http::Uri::try_from(format!(
+            "http://127.0.0.1:{}/app/",
+            mock_server.address().port()
+        ))
+        .unwrap();
+        let strangler = StranglerBuilder::from_base_uri(base_uri)
+            .unwrap()
+            .with_rewrite(RewriteRule::strip_prefix("/legacy"))
+            .build();
+
+        let response = strangler
+            .forward_to_strangled(
+                axum::http::Request::get("/legacy/users")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn try_forward_tells_apart_upstream_errors_from_unreachable() {
+        let mock_server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/broken"))
+            .respond_with(wiremock::ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        let response = strangler
+            .try_forward_to_strangled(
+                axum::http::Request::get("/broken")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let strangler =
+            Strangler::new(axum::http::uri::Authority::try_from(address.to_string()).unwrap());
+        let error = strangler
+            .try_forward_to_strangled(
+                axum::http::Request::get("/broken")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, StranglerError::Connect(_)));
+    }
+
+    #[tokio::test]
+    async fn instance_is_busy_until_the_body_is_sent() {
+        use futures_util::StreamExt;
+
+        let stranglee_tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority =
+            axum::http::uri::Authority::try_from(stranglee_tcp.local_addr().unwrap().to_string())
+                .unwrap();
+        let router = Router::new().route(
+            "/slow",
+            get(|| async {
+                let slow = futures_util::stream::once(async { Ok::<_, std::io::Error>("hello ") })
+                    .chain(futures_util::stream::once(async {
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok("world")
+                    }));
+                axum::body::StreamBody::new(slow)
+            }),
+        );
+        tokio::spawn(
+            axum::Server::from_tcp(stranglee_tcp)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        let strangler = Strangler::new(authority);
+        let response = strangler
+            .forward_to_strangled(
+                axum::http::Request::get("/slow")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(strangler.health().upstreams()[0].outstanding_requests(), 1);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "hello world");
+        assert_eq!(strangler.health().upstreams()[0].outstanding_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn open_circuit_fails_fast() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = axum::http::uri::Authority::try_from(format!(
+            "127.0.0.1:{}",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        drop(listener);
+
+        let strangler = Strangler::builder(authority)
+            .with_circuit_breaker(CircuitBreaker::new().with_consecutive_failures(1))
+            .build();
+        let request = || {
+            axum::http::Request::get("/api/something")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = strangler.forward_to_strangled(request()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_GATEWAY);
+        assert_eq!(strangler.health().circuit(), CircuitState::Open);
+
+        let response = strangler.forward_to_strangled(request()).await;
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(response.headers()["retry-after"], "30");
+    }
+
+    #[cfg(feature = "health-check")]
+    #[tokio::test]
+    async fn health_checks_mark_failing_instances_unhealthy() {
+        let mock_server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/healthz"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let strangler = Strangler::builder(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        )
+        .with_health_check(
+            HealthCheck::new("/healthz")
+                .with_interval(std::time::Duration::from_millis(10))
+                .with_unhealthy_threshold(1),
+        )
+        .build();
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let health = strangler.health();
+        assert!(!health.upstreams()[0].is_healthy());
+        assert!(!health.is_healthy());
+    }
+
+    #[cfg(feature = "health-check")]
+    #[tokio::test]
+    async fn health_checks_probe_below_the_base_path() {
+        let mock_server = wiremock::MockServer::start().await;
+        // Anything else is a 404, which makes the instance unhealthy.
+        wiremock::Mock::given(wiremock::matchers::path("/app/healthz"))
+            .respond_with(wiremock::ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let strangler =
+            StranglerBuilder::from_base_uri(format!("{}/app", mock_server.uri()).parse().unwrap())
+                .unwrap()
+                .with_health_check(
+                    HealthCheck::new("/healthz")
+                        .with_interval(std::time::Duration::from_millis(10))
+                        .with_unhealthy_threshold(1),
+                )
+                .build();
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert!(strangler.health().upstreams()[0].is_healthy());
+    }
+
+    #[cfg(feature = "health-check")]
+    #[tokio::test]
+    async fn health_checks_probe_instances_concurrently() {
+        let slow = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/healthz"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_millis(500)),
+            )
+            .mount(&slow)
+            .await;
+        let failing = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/healthz"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .mount(&failing)
+            .await;
+
+        let strangler = Strangler::builder_for_pool([&slow, &failing].map(|server| {
+            axum::http::uri::Authority::try_from(server.address().to_string()).unwrap()
+        }))
+        .with_health_check(
+            HealthCheck::new("/healthz")
+                .with_interval(std::time::Duration::from_millis(10))
+                .with_unhealthy_threshold(1),
+        )
+        .build();
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let health = strangler.health();
+        assert!(health.upstreams()[0].is_healthy());
+        assert!(!health.upstreams()[1].is_healthy());
+    }
 }
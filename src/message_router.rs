@@ -0,0 +1,74 @@
+use axum::extract::ws::Message;
+
+/// What happens with a websocket message, see [`WebSocketMessageRouter`].
+#[derive(Debug)]
+pub enum MessageAction {
+    /// Pass the message, or a rewritten one, on to the other side.
+    Forward(Message),
+    /// Don't pass the message on, but send this message back to the side it came from.
+    Reply(Message),
+    /// Don't pass the message on.
+    Drop,
+}
+
+/// Inspects the text and binary messages of a proxied websocket connection, to take over message
+/// types from the strangled service one at a time. Pings, pongs and close frames are passed on as
+/// is.
+/// Every connection gets its own router, made from the handshake request by the function
+/// registered with [`crate::StranglerBuilder::with_web_socket_message_router`], so it can keep
+/// state for the connection.
+/// ```rust
+/// use axum::{extract::ws::Message, http::request::Parts};
+/// use axum_strangler::{MessageAction, WebSocketMessageRouter};
+///
+/// /// Answers `{"type": "count"}` messages itself with the number of messages the user sent on
+/// /// this connection, and passes everything else on.
+/// struct Count {
+///     user: String,
+///     messages: usize,
+/// }
+///
+/// #[axum::async_trait]
+/// impl WebSocketMessageRouter for Count {
+///     async fn on_client_message(&mut self, message: Message) -> MessageAction {
+///         self.messages += 1;
+///         let envelope = match &message {
+///             Message::Text(text) => serde_json::from_str::<serde_json::Value>(text).ok(),
+///             _ => None,
+///         };
+///         match envelope {
+///             Some(envelope) if envelope["type"] == "count" => MessageAction::Reply(Message::Text(
+///                 format!(r#"{{"type": "count", "user": "{}", "count": {}}}"#, self.user, self.messages),
+///             )),
+///             _ => MessageAction::Forward(message),
+///         }
+///     }
+/// }
+///
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_web_socket_message_router(|parts: &Parts| Count {
+///     user: parts
+///         .headers
+///         .get("x-user")
+///         .and_then(|user| user.to_str().ok())
+///         .unwrap_or_default()
+///         .to_owned(),
+///     messages: 0,
+/// })
+/// .build();
+/// ```
+#[axum::async_trait]
+pub trait WebSocketMessageRouter: Send {
+    /// Called with every message of the client. Replies go back to the client.
+    async fn on_client_message(&mut self, message: Message) -> MessageAction {
+        MessageAction::Forward(message)
+    }
+
+    /// Called with every message of the strangled service. Replies go back to the strangled
+    /// service.
+    async fn on_strangled_message(&mut self, message: Message) -> MessageAction {
+        MessageAction::Forward(message)
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, put},
+};
+
+/// Where the requests for a route go, see [`RouteRegistry`] and [`crate::RoutingDecider`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteState {
+    /// The new implementation handles the requests.
+    New,
+    /// The requests are forwarded to the strangled service.
+    Legacy,
+    /// The requests are forwarded to the strangled service, and mirrored to the new
+    /// implementation, see [`crate::Shadow`].
+    Shadow,
+}
+
+impl RouteState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RouteState::New => "new",
+            RouteState::Legacy => "legacy",
+            RouteState::Shadow => "shadow",
+        }
+    }
+}
+
+impl std::fmt::Display for RouteState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RouteState {
+    type Err = UnknownRouteState;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "new" => Ok(RouteState::New),
+            "legacy" => Ok(RouteState::Legacy),
+            "shadow" => Ok(RouteState::Shadow),
+            _ => Err(UnknownRouteState),
+        }
+    }
+}
+
+/// Returned when parsing something other than `new`, `legacy` or `shadow` as a [`RouteState`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownRouteState;
+
+impl std::fmt::Display for UnknownRouteState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected `new`, `legacy` or `shadow`")
+    }
+}
+
+impl std::error::Error for UnknownRouteState {}
+
+/// Keeps track of the [`RouteState`] of migrated routes, so a route can be sent back to the
+/// strangled service at runtime, without a deploy.
+/// All clones share the same states. A [`crate::RouteSwitch`] consults the registry for every
+/// request.
+/// ```rust
+/// use axum::routing::get;
+/// use axum_strangler::{RouteRegistry, RouteState, RouteSwitch, Strangler};
+///
+/// let registry = RouteRegistry::new();
+/// let strangler = Strangler::new(axum::http::uri::Authority::from_static("127.0.0.1:3333"));
+/// let users = axum::Router::new().route("/users", get(|| async { "users" }));
+///
+/// let app = axum::Router::new()
+///     .route(
+///         "/users",
+///         RouteSwitch::new(users, strangler.clone(), registry.route("users", RouteState::New)),
+///     )
+///     .nest("/admin/routes", registry.admin_router())
+///     .fallback(strangler);
+///
+/// // The new implementation misbehaves, send the route back to the strangled service:
+/// registry.set("users", RouteState::Legacy);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RouteRegistry {
+    routes: Arc<RwLock<BTreeMap<String, RouteState>>>,
+}
+
+impl RouteRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the route `key` with `state`, unless it's already registered, and returns a
+    /// handle to its current state.
+    pub fn route(&self, key: impl Into<String>, state: RouteState) -> RegisteredRoute {
+        let key = key.into();
+        self.routes
+            .write()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert(state);
+        RegisteredRoute {
+            registry: self.clone(),
+            key,
+        }
+    }
+
+    /// Changes the state of the route `key`, returns the previous state, or `None` if the route
+    /// isn't registered, in which case nothing changes.
+    pub fn set(&self, key: &str, state: RouteState) -> Option<RouteState> {
+        let previous = self
+            .routes
+            .write()
+            .unwrap()
+            .get_mut(key)
+            .map(|current| std::mem::replace(current, state));
+        if let Some(previous) = previous {
+            tracing::info!(route = key, %previous, %state, "changed route state");
+        }
+        previous
+    }
+
+    pub fn state(&self, key: &str) -> Option<RouteState> {
+        self.routes.read().unwrap().get(key).copied()
+    }
+
+    /// All registered routes and their states, ordered by key.
+    pub fn routes(&self) -> Vec<(String, RouteState)> {
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, state)| (key.clone(), *state))
+            .collect()
+    }
+
+    /// A router to inspect and change the route states over HTTP, meant to be nested under some
+    /// admin path. Make sure to protect it, e.g. with an auth layer.
+    ///
+    /// - `GET /` lists every route as a `key: state` line.
+    /// - `PUT /:key` with `new`, `legacy` or `shadow` as the body changes the state of a route.
+    pub fn admin_router(&self) -> axum::Router {
+        let list = self.clone();
+        let update = self.clone();
+        axum::Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    list.routes()
+                        .into_iter()
+                        .map(|(key, state)| format!("{}: {}\n", key, state))
+                        .collect::<String>()
+                }),
+            )
+            .route(
+                "/:key",
+                put(move |Path(key): Path<String>, body: String| async move {
+                    let state = match body.parse::<RouteState>() {
+                        Ok(state) => state,
+                        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+                    };
+                    match update.set(&key, state) {
+                        Some(_) => StatusCode::NO_CONTENT.into_response(),
+                        None => StatusCode::NOT_FOUND.into_response(),
+                    }
+                }),
+            )
+    }
+}
+
+/// The state of a single route in a [`RouteRegistry`].
+#[derive(Clone, Debug)]
+pub struct RegisteredRoute {
+    registry: RouteRegistry,
+    key: String,
+}
+
+impl RegisteredRoute {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn state(&self) -> RouteState {
+        self.registry.state(&self.key).unwrap_or(RouteState::Legacy)
+    }
+
+    pub fn set(&self, state: RouteState) {
+        self.registry.set(&self.key, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower_service::Service;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_state_of_registered_routes() {
+        let registry = RouteRegistry::new();
+        let users = registry.route("users", RouteState::New);
+        assert_eq!(
+            registry.route("users", RouteState::Shadow).state(),
+            RouteState::New
+        );
+
+        assert_eq!(
+            registry.set("users", RouteState::Legacy),
+            Some(RouteState::New)
+        );
+        assert_eq!(users.state(), RouteState::Legacy);
+        assert_eq!(registry.set("orders", RouteState::New), None);
+        assert_eq!(
+            registry.routes(),
+            [("users".to_owned(), RouteState::Legacy)]
+        );
+    }
+
+    async fn call(admin: &axum::Router, req: Request<Body>) -> (StatusCode, String) {
+        let response = admin.clone().call(req).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn admin_router_changes_states() {
+        let registry = RouteRegistry::new();
+        let users = registry.route("users", RouteState::New);
+        let admin = registry.admin_router();
+        let put = |uri: &str, body: &'static str| Request::put(uri).body(Body::from(body)).unwrap();
+
+        let (status, _) = call(&admin, put("/users", "shadow")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(users.state(), RouteState::Shadow);
+
+        let (status, _) = call(&admin, put("/orders", "new")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&admin, put("/users", "old")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let listed = call(&admin, Request::get("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(listed, (StatusCode::OK, "users: shadow\n".to_owned()));
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+
+use crate::StranglerError;
+
+/// Decides whether, and how often, a request to the strangled service is attempted again when it
+/// fails.
+///
+/// By default, only idempotent requests (`GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and `TRACE`)
+/// without a body are retried, when the strangled service couldn't be reached or answered with
+/// `502 Bad Gateway`, `503 Service Unavailable` or `504 Gateway Timeout`.
+/// ```rust
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_retry_policy(
+///     axum_strangler::RetryPolicy::new(3)
+///         .with_max_replay_body_size(64 * 1024),
+/// )
+/// .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retryable_status_codes: Vec<StatusCode>,
+    retry_non_idempotent_methods: bool,
+    max_replay_body_size: usize,
+}
+
+impl RetryPolicy {
+    /// `max_attempts` includes the first attempt, so `RetryPolicy::new(1)` never retries.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            retryable_status_codes: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent_methods: false,
+            max_replay_body_size: 0,
+        }
+    }
+
+    /// The backoff doubles after every attempt, starting from `initial_backoff` up until
+    /// `max_backoff`. A random jitter is applied, so the actual wait is somewhere between zero and
+    /// the backoff.
+    /// The default is to start at 50ms, up until 2s.
+    pub fn with_backoff(self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// The status codes of the strangled service's response that cause a retry.
+    /// The default is `502`, `503` and `504`.
+    pub fn with_retryable_status_codes(
+        self,
+        retryable_status_codes: impl IntoIterator<Item = StatusCode>,
+    ) -> Self {
+        Self {
+            retryable_status_codes: retryable_status_codes.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Whether or not to also retry e.g. `POST` requests. Only do this if the strangled service
+    /// can cope with receiving the same request more than once.
+    pub fn retry_non_idempotent_methods(self, retry_non_idempotent_methods: bool) -> Self {
+        Self {
+            retry_non_idempotent_methods,
+            ..self
+        }
+    }
+
+    /// In order to retry a request with a body, the body has to be kept around. Bodies up to this
+    /// size are buffered, larger ones are forwarded without retrying.
+    /// The default is `0`, so requests with a body aren't retried.
+    pub fn with_max_replay_body_size(self, max_replay_body_size: usize) -> Self {
+        Self {
+            max_replay_body_size,
+            ..self
+        }
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub(crate) fn max_replay_body_size(&self) -> usize {
+        self.max_replay_body_size
+    }
+
+    pub(crate) fn allows_method(&self, method: &Method) -> bool {
+        self.retry_non_idempotent_methods
+            || matches!(
+                *method,
+                Method::GET
+                    | Method::HEAD
+                    | Method::PUT
+                    | Method::DELETE
+                    | Method::OPTIONS
+                    | Method::TRACE
+            )
+    }
+
+    pub(crate) fn should_retry<B>(
+        &self,
+        result: &Result<axum::http::Response<B>, StranglerError>,
+    ) -> bool {
+        match result {
+            Ok(response) => self.retryable_status_codes.contains(&response.status()),
+            Err(e) => matches!(
+                e,
+                StranglerError::Connect(_) | StranglerError::ConnectTimeout(_)
+            ),
+        }
+    }
+
+    /// The time to wait after the given (1-based) attempt failed.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let backoff = exponential.min(self.max_backoff);
+        backoff.mul_f64(fastrand::f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_max() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(300));
+
+        for _ in 0..100 {
+            assert!(policy.backoff(1) <= Duration::from_millis(100));
+            assert!(policy.backoff(2) <= Duration::from_millis(200));
+            assert!(policy.backoff(8) <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn only_idempotent_methods_by_default() {
+        let policy = RetryPolicy::new(3);
+        assert!(policy.allows_method(&Method::GET));
+        assert!(policy.allows_method(&Method::PUT));
+        assert!(!policy.allows_method(&Method::POST));
+        assert!(!policy.allows_method(&Method::PATCH));
+
+        let policy = policy.retry_non_idempotent_methods(true);
+        assert!(policy.allows_method(&Method::POST));
+    }
+}
@@ -0,0 +1,199 @@
+/// Changes the path or query of a request before it's forwarded to the strangled service.
+/// Rules are applied in the order they were added to the `StranglerBuilder`.
+/// ```rust
+/// use axum_strangler::RewriteRule;
+///
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_rewrite(RewriteRule::strip_prefix("/legacy"))
+/// .with_rewrite(RewriteRule::regex(r"^/users/(\d+)$", "/user.php?id=$1").unwrap())
+/// .with_rewrite(RewriteRule::rename_query_param("q", "query"))
+/// .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct RewriteRule(Rule);
+
+#[derive(Clone, Debug)]
+enum Rule {
+    StripPrefix(String),
+    AddPrefix(String),
+    Regex(regex::Regex, String),
+    AddQueryParam(String, String),
+    RemoveQueryParam(String),
+    RenameQueryParam(String, String),
+}
+
+impl RewriteRule {
+    /// Removes `prefix` from the path, if the path starts with it.
+    pub fn strip_prefix(prefix: impl Into<String>) -> Self {
+        Self(Rule::StripPrefix(
+            prefix.into().trim_end_matches('/').to_owned(),
+        ))
+    }
+
+    /// Puts `prefix` in front of the path.
+    pub fn add_prefix(prefix: impl Into<String>) -> Self {
+        Self(Rule::AddPrefix(
+            prefix.into().trim_end_matches('/').to_owned(),
+        ))
+    }
+
+    /// Replaces the path if it matches `pattern`. The `replacement` can refer to capture groups
+    /// like `$1` or `$name`, see [`regex::Regex::replace`], and may contain a query which is
+    /// merged with the query of the request.
+    pub fn regex(pattern: &str, replacement: impl Into<String>) -> Result<Self, regex::Error> {
+        Ok(Self(Rule::Regex(
+            regex::Regex::new(pattern)?,
+            replacement.into(),
+        )))
+    }
+
+    /// Adds a query parameter, `name` and `value` have to be url-encoded already.
+    pub fn add_query_param(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self(Rule::AddQueryParam(name.into(), value.into()))
+    }
+
+    /// Removes every query parameter called `name`.
+    pub fn remove_query_param(name: impl Into<String>) -> Self {
+        Self(Rule::RemoveQueryParam(name.into()))
+    }
+
+    /// Renames every query parameter called `from` to `to`.
+    pub fn rename_query_param(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self(Rule::RenameQueryParam(from.into(), to.into()))
+    }
+
+    fn apply(&self, path: &mut String, query: &mut Vec<String>) {
+        match &self.0 {
+            Rule::StripPrefix(prefix) => {
+                if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                    if rest.is_empty() {
+                        *path = "/".to_owned();
+                    } else if rest.starts_with('/') {
+                        *path = rest.to_owned();
+                    }
+                }
+            }
+            Rule::AddPrefix(prefix) => {
+                *path = if path == "/" {
+                    format!("{}/", prefix)
+                } else {
+                    format!("{}{}", prefix, path)
+                };
+            }
+            Rule::Regex(regex, replacement) => {
+                if !regex.is_match(path) {
+                    return;
+                }
+                let replaced = regex.replace(path, replacement.as_str()).into_owned();
+                match replaced.split_once('?') {
+                    Some((new_path, new_query)) => {
+                        *path = new_path.to_owned();
+                        query.extend(split_query(new_query));
+                    }
+                    None => *path = replaced,
+                }
+            }
+            Rule::AddQueryParam(name, value) => query.push(format!("{}={}", name, value)),
+            Rule::RemoveQueryParam(name) => query.retain(|param| param_name(param) != name),
+            Rule::RenameQueryParam(from, to) => {
+                for param in query.iter_mut() {
+                    if param_name(param) == from {
+                        *param = format!("{}{}", to, &param[from.len()..]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn split_query(query: &str) -> impl Iterator<Item = String> + '_ {
+    query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+fn param_name(param: &str) -> &str {
+    param.split_once('=').map_or(param, |(name, _)| name)
+}
+
+/// Applies all `rules` to the path and query of `uri`.
+pub(crate) fn rewrite(
+    rules: &[RewriteRule],
+    uri: &axum::http::Uri,
+) -> Result<axum::http::uri::PathAndQuery, axum::http::Error> {
+    let path_and_query = uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| axum::http::uri::PathAndQuery::from_static("/"));
+    if rules.is_empty() {
+        return Ok(path_and_query);
+    }
+
+    let mut path = path_and_query.path().to_owned();
+    let mut query: Vec<String> = path_and_query
+        .query()
+        .map(|query| split_query(query).collect())
+        .unwrap_or_default();
+    for rule in rules {
+        rule.apply(&mut path, &mut query);
+    }
+
+    let path_and_query = if query.is_empty() {
+        path
+    } else {
+        format!("{}?{}", path, query.join("&"))
+    };
+    Ok(axum::http::uri::PathAndQuery::try_from(path_and_query)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewritten(rules: &[RewriteRule], uri: &'static str) -> String {
+        rewrite(rules, &axum::http::Uri::from_static(uri))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn prefixes() {
+        let rules = [
+            RewriteRule::strip_prefix("/legacy/"),
+            RewriteRule::add_prefix("/app/v1"),
+        ];
+
+        assert_eq!(rewritten(&rules, "/legacy/users?a=b"), "/app/v1/users?a=b");
+        assert_eq!(rewritten(&rules, "/legacy"), "/app/v1/");
+        assert_eq!(rewritten(&rules, "/legacyfoo"), "/app/v1/legacyfoo");
+    }
+
+    #[test]
+    fn regex_with_capture_groups() {
+        let rules = [RewriteRule::regex(r"^/users/(?P<id>\d+)$", "/user.php?id=$id").unwrap()];
+
+        assert_eq!(
+            rewritten(&rules, "/users/42?lang=en"),
+            "/user.php?lang=en&id=42"
+        );
+        assert_eq!(rewritten(&rules, "/users/me"), "/users/me");
+    }
+
+    #[test]
+    fn query_params() {
+        let rules = [
+            RewriteRule::remove_query_param("debug"),
+            RewriteRule::rename_query_param("q", "query"),
+            RewriteRule::add_query_param("source", "strangler"),
+        ];
+
+        assert_eq!(
+            rewritten(&rules, "/search?q=rust&debug=1&qq=2"),
+            "/search?query=rust&qq=2&source=strangler"
+        );
+        assert_eq!(rewritten(&rules, "/search"), "/search?source=strangler");
+    }
+}
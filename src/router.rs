@@ -0,0 +1,327 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    http::{uri::PathAndQuery, Method, StatusCode, Uri},
+    response::IntoResponse,
+};
+use tower_service::Service;
+
+use crate::Strangler;
+
+/// Decides which requests are sent to which strangled service by a [`StranglerRouter`].
+/// All the conditions that are set have to match.
+#[derive(Clone, Debug, Default)]
+pub struct StranglerRoute {
+    prefix: Option<String>,
+    host: Option<String>,
+    method: Option<Method>,
+    strip_prefix: bool,
+}
+
+impl StranglerRoute {
+    /// Matches requests of which the path is `prefix`, or starts with `prefix` followed by a `/`.
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self::default().and_prefix(prefix)
+    }
+
+    /// Matches requests for `host`, taken from the `host` header or the request's uri, ignoring
+    /// the port.
+    pub fn host(host: impl Into<String>) -> Self {
+        Self::default().and_host(host)
+    }
+
+    /// Matches requests with `method`.
+    pub fn method(method: Method) -> Self {
+        Self::default().and_method(method)
+    }
+
+    pub fn and_prefix(self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        Self {
+            prefix: Some(prefix.trim_end_matches('/').to_owned()),
+            ..self
+        }
+    }
+
+    pub fn and_host(self, host: impl Into<String>) -> Self {
+        Self {
+            host: Some(host.into().to_ascii_lowercase()),
+            ..self
+        }
+    }
+
+    pub fn and_method(self, method: Method) -> Self {
+        Self {
+            method: Some(method),
+            ..self
+        }
+    }
+
+    /// Removes the prefix from the path before forwarding the request, so a request for
+    /// `/billing/invoices` matching the `/billing` prefix is forwarded as `/invoices`.
+    pub fn strip_prefix(self) -> Self {
+        Self {
+            strip_prefix: true,
+            ..self
+        }
+    }
+
+    /// Returns how specific the match is, or `None` if the request doesn't match.
+    fn matches<B>(&self, req: &axum::http::Request<B>) -> Option<(usize, bool, bool)> {
+        if let Some(prefix) = &self.prefix {
+            let path = req.uri().path();
+            let rest = path.strip_prefix(prefix.as_str())?;
+            if !(rest.is_empty() || rest.starts_with('/')) {
+                return None;
+            }
+        }
+        if let Some(host) = &self.host {
+            if !request_host(req)
+                .is_some_and(|request_host| request_host.eq_ignore_ascii_case(host))
+            {
+                return None;
+            }
+        }
+        if let Some(method) = &self.method {
+            if req.method() != method {
+                return None;
+            }
+        }
+
+        Some((
+            self.prefix.as_ref().map_or(0, |prefix| prefix.len() + 1),
+            self.host.is_some(),
+            self.method.is_some(),
+        ))
+    }
+
+    fn strip<B>(&self, req: &mut axum::http::Request<B>) {
+        let prefix = match (&self.prefix, self.strip_prefix) {
+            (Some(prefix), true) => prefix,
+            _ => return,
+        };
+
+        let path = req.uri().path().strip_prefix(prefix.as_str()).unwrap_or("");
+        let path_and_query = match (path, req.uri().query()) {
+            ("", Some(query)) => format!("/?{}", query),
+            ("", None) => "/".to_owned(),
+            (path, Some(query)) => format!("{}?{}", path, query),
+            (path, None) => path.to_owned(),
+        };
+
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+}
+
+fn request_host<B>(req: &axum::http::Request<B>) -> Option<&str> {
+    let host = req
+        .headers()
+        .get(axum::http::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().host())?;
+    // Strip the port, taking care of ipv6 addresses like `[::1]:3000`.
+    match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => Some(host),
+        _ => Some(host),
+    }
+}
+
+/// Sends requests to one of several strangled services, e.g. when a monolith really consists of
+/// several legacy services behind a single domain.
+/// When multiple routes match, the one with the longest prefix wins. Requests that don't match
+/// any route go to the fallback, or get a `404 Not Found` when there is none.
+/// ```rust
+/// use axum::http::uri::Authority;
+/// use axum_strangler::{Strangler, StranglerRoute, StranglerRouter};
+///
+/// let router = StranglerRouter::new()
+///     .route(
+///         StranglerRoute::prefix("/billing").strip_prefix(),
+///         Strangler::new(Authority::from_static("127.0.0.1:3333")),
+///     )
+///     .route(
+///         StranglerRoute::prefix("/shop"),
+///         Strangler::new(Authority::from_static("127.0.0.1:3334")),
+///     )
+///     .fallback(Strangler::new(Authority::from_static("127.0.0.1:3335")));
+///
+/// let app = axum::Router::new().fallback(router);
+/// ```
+#[derive(Clone, Default)]
+pub struct StranglerRouter {
+    routes: Arc<Vec<(StranglerRoute, Strangler)>>,
+    fallback: Option<Strangler>,
+}
+
+impl StranglerRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, route: StranglerRoute, strangler: Strangler) -> Self {
+        Arc::make_mut(&mut self.routes).push((route, strangler));
+        self
+    }
+
+    /// Where to send requests that don't match any route.
+    pub fn fallback(self, strangler: Strangler) -> Self {
+        Self {
+            fallback: Some(strangler),
+            ..self
+        }
+    }
+
+    /// Forwards the request to the strangled service that matches it, see
+    /// [`Strangler::forward_to_strangled`].
+    pub async fn forward_to_strangled(
+        &self,
+        mut req: axum::http::Request<axum::body::Body>,
+    ) -> axum::response::Response {
+        let mut best: Option<(_, &(StranglerRoute, Strangler))> = None;
+        for route in self.routes.iter() {
+            if let Some(specificity) = route.0.matches(&req) {
+                if best.map_or(true, |(best_specificity, _)| specificity > best_specificity) {
+                    best = Some((specificity, route));
+                }
+            }
+        }
+
+        match best {
+            Some((_, (route, strangler))) => {
+                route.strip(&mut req);
+                strangler.forward_to_strangled(req).await
+            }
+            None => match &self.fallback {
+                Some(strangler) => strangler.forward_to_strangled(req).await,
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+        }
+    }
+}
+
+impl Service<axum::http::Request<axum::body::Body>> for StranglerRouter {
+    type Response = axum::response::Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: axum::http::Request<axum::body::Body>) -> Self::Future {
+        let this = self.clone();
+
+        let fut = async move { Ok(this.forward_to_strangled(req).await) };
+        Box::pin(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    fn request(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri)
+            .header("host", "example.com:8080")
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn prefix_only_matches_whole_segments() {
+        let route = StranglerRoute::prefix("/billing/");
+
+        assert!(route.matches(&request("/billing")).is_some());
+        assert!(route.matches(&request("/billing/invoices")).is_some());
+        assert!(route.matches(&request("/billingsomething")).is_none());
+        assert!(route.matches(&request("/shop")).is_none());
+    }
+
+    #[test]
+    fn matches_host_and_method() {
+        let route = StranglerRoute::host("Example.com").and_method(Method::GET);
+        assert!(route.matches(&request("/")).is_some());
+
+        let route = StranglerRoute::host("other.com");
+        assert!(route.matches(&request("/")).is_none());
+
+        let route = StranglerRoute::method(Method::POST);
+        assert!(route.matches(&request("/")).is_none());
+    }
+
+    #[test]
+    fn strips_prefix() {
+        let route = StranglerRoute::prefix("/billing").strip_prefix();
+
+        let mut req = request("/billing/invoices?page=2");
+        route.strip(&mut req);
+        assert_eq!(req.uri(), "/invoices?page=2");
+
+        let mut req = request("/billing");
+        route.strip(&mut req);
+        assert_eq!(req.uri(), "/");
+    }
+
+    async fn mock_server_answering(status: u16) -> (MockServer, Strangler) {
+        let mock_server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+        (mock_server, strangler)
+    }
+
+    #[tokio::test]
+    async fn longest_prefix_wins() {
+        let (_short, short) = mock_server_answering(201).await;
+        let (long, long_strangler) = mock_server_answering(202).await;
+        let (_fallback, fallback) = mock_server_answering(203).await;
+        Mock::given(path("/invoices"))
+            .respond_with(ResponseTemplate::new(204))
+            .with_priority(1)
+            .mount(&long)
+            .await;
+
+        let router = StranglerRouter::new()
+            .route(StranglerRoute::prefix("/billing"), short)
+            .route(
+                StranglerRoute::prefix("/billing/v2").strip_prefix(),
+                long_strangler,
+            )
+            .fallback(fallback);
+
+        let status = |uri: &'static str| {
+            let router = router.clone();
+            async move { router.forward_to_strangled(request(uri)).await.status() }
+        };
+        assert_eq!(status("/billing/v1").await, 201);
+        assert_eq!(status("/billing/v2/invoices").await, 204);
+        assert_eq!(status("/shop").await, 203);
+        assert_eq!(
+            StranglerRouter::new()
+                .forward_to_strangled(request("/"))
+                .await
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    future::Future,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, BoxBody, Bytes, HttpBody},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    response::Response,
+    BoxError,
+};
+use tokio::sync::oneshot;
+use tower_service::Service;
+
+use crate::{
+    inner::body::{buffer_body, clone_request, Buffered},
+    CapturedResponse, Difference, ResponseComparator, StranglerError,
+};
+
+type MirrorTarget = Arc<
+    dyn Fn(Request<Body>) -> Pin<Box<dyn Future<Output = Result<Response, BoxError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// Sends a copy of the forwarded requests to another service, e.g. the new implementation of a
+/// route that isn't live yet, and discards its responses.
+/// The strangled service stays authoritative: the copies are sent in the background, so they
+/// don't slow down the forwarded requests.
+/// Requires a tokio runtime.
+///
+/// With a [`ResponseComparator`] the responses are compared instead of discarded, and mismatches
+/// are reported as `tracing` events.
+/// ```rust
+/// use axum::routing::get;
+/// use axum_strangler::Shadow;
+///
+/// let new_implementation = axum::Router::new().route("/users", get(|| async { "users" }));
+/// let strangler_svc = axum_strangler::Strangler::builder(
+///     axum::http::uri::Authority::from_static("127.0.0.1:3333"),
+/// )
+/// .with_shadow(Shadow::new(new_implementation).with_sample_rate(0.1))
+/// .build();
+/// ```
+#[derive(Clone)]
+pub struct Shadow {
+    target: MirrorTarget,
+    sample_rate: f64,
+    methods: Vec<Method>,
+    max_body_size: usize,
+    comparator: Option<Arc<dyn ResponseComparator>>,
+    mismatch_log: Option<PathBuf>,
+}
+
+impl Shadow {
+    /// Mirrors every `GET` request with a body of up to 64 KiB to `target`, which can be e.g. an
+    /// axum `Router` or another [`crate::Strangler`].
+    pub fn new<S, ResBody>(target: S) -> Self
+    where
+        S: Service<Request<Body>, Response = axum::http::Response<ResBody>>
+            + Clone
+            + Send
+            + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send,
+        ResBody: HttpBody<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        let target = Mutex::new(target);
+        Self {
+            target: Arc::new(move |req| {
+                let mut target = target.lock().unwrap().clone();
+                Box::pin(async move {
+                    std::future::poll_fn(|cx| target.poll_ready(cx))
+                        .await
+                        .map_err(Into::into)?;
+                    let response = target.call(req).await.map_err(Into::into)?;
+                    Ok(response.map(axum::body::boxed))
+                })
+            }),
+            sample_rate: 1.0,
+            methods: vec![Method::GET],
+            max_body_size: 64 * 1024,
+            comparator: None,
+            mismatch_log: None,
+        }
+    }
+
+    /// The fraction of requests to mirror, between `0.0` and `1.0`. The default is `1.0`.
+    pub fn with_sample_rate(self, sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            ..self
+        }
+    }
+
+    /// Which request methods to mirror. Only `GET` requests are mirrored by default, so the new
+    /// implementation doesn't write the same data as the strangled service.
+    pub fn with_methods(self, methods: impl IntoIterator<Item = Method>) -> Self {
+        Self {
+            methods: methods.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Compare the response of the shadow target with the strangled service's, see
+    /// [`crate::JsonComparator`].
+    /// Response bodies are compared up to the maximum body size.
+    pub fn with_comparator<C>(self, comparator: C) -> Self
+    where
+        C: ResponseComparator + 'static,
+    {
+        Self {
+            comparator: Some(Arc::new(comparator)),
+            ..self
+        }
+    }
+
+    /// Besides emitting a `tracing` event, append every mismatch to the file at `path` as a line
+    /// of JSON.
+    pub fn with_mismatch_log(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            mismatch_log: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Requests with a larger body, in bytes, aren't mirrored.
+    pub fn with_max_body_size(self, max_body_size: usize) -> Self {
+        Self {
+            max_body_size,
+            ..self
+        }
+    }
+
+    fn should_mirror(&self, req: &Request<Body>) -> bool {
+        self.methods.contains(req.method())
+            && !req.headers().contains_key(header::UPGRADE)
+            && fastrand::f64() < self.sample_rate
+    }
+
+    /// Sends a copy of `req` to the shadow target in the background, returns the request to
+    /// forward to the strangled service.
+    /// If the responses are compared, the response of the strangled service has to be passed to
+    /// the returned [`Comparison`].
+    pub(crate) async fn mirror(
+        &self,
+        req: Request<Body>,
+    ) -> Result<(Request<Body>, Option<Comparison>), StranglerError> {
+        if !self.should_mirror(&req) {
+            return Ok((req, None));
+        }
+
+        let (parts, body) = req.into_parts();
+        let body = match buffer_body(body, self.max_body_size).await? {
+            Buffered::Complete(body) => body,
+            Buffered::TooLarge(body) => {
+                tracing::debug!("request body is too large to be mirrored");
+                return Ok((Request::from_parts(parts, body), None));
+            }
+        };
+
+        let mut mirrored = clone_request(&parts, body.clone());
+        if let Some(connect_info) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            mirrored.extensions_mut().insert(*connect_info);
+        }
+
+        let (comparison, legacy) = match &self.comparator {
+            Some(comparator) => {
+                let (tx, rx) = oneshot::channel();
+                let comparison = Comparison {
+                    legacy: tx,
+                    max_body_size: self.max_body_size,
+                };
+                (Some(comparison), Some((rx, comparator.clone())))
+            }
+            None => (None, None),
+        };
+        let target = self.target.clone();
+        let max_body_size = self.max_body_size;
+        let mismatch_log = self.mismatch_log.clone();
+        tokio::spawn(async move {
+            let method = mirrored.method().clone();
+            let uri = mirrored.uri().clone();
+            let response = match target(mirrored).await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::debug!(%uri, error = %e, "mirroring request failed");
+                    return;
+                }
+            };
+            tracing::debug!(%uri, status = %response.status(), "mirrored request");
+
+            let (legacy, comparator) = match legacy {
+                Some(legacy) => legacy,
+                None => return,
+            };
+            let new = capture(response, max_body_size).await;
+            let legacy = match legacy.await {
+                Ok(legacy) => legacy,
+                Err(_) => {
+                    tracing::debug!(%uri, "no complete response from the strangled service to compare");
+                    return;
+                }
+            };
+
+            let differences = comparator.compare(&legacy, &new);
+            if differences.is_empty() {
+                tracing::debug!(%method, %uri, "mirrored response matches the strangled service");
+                return;
+            }
+            // The differences themselves go to the mismatch log.
+            tracing::warn!(
+                %method,
+                path = uri.path(),
+                differences = differences.len(),
+                "mirrored response differs from the strangled service"
+            );
+            if let Some(path) = mismatch_log {
+                let line = mismatch_line(&method, &uri, &differences);
+                let written = tokio::task::spawn_blocking(move || {
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)?
+                        .write_all(line.as_bytes())
+                })
+                .await;
+                if let Ok(Err(e)) = written {
+                    tracing::error!(error = %e, "could not write to the mismatch log");
+                }
+            }
+        });
+
+        Ok((Request::from_parts(parts, Body::from(body)), comparison))
+    }
+}
+
+fn mismatch_line(method: &Method, uri: &axum::http::Uri, differences: &[Difference]) -> String {
+    let differences: Vec<serde_json::Value> = differences
+        .iter()
+        .map(|difference| {
+            serde_json::json!({
+                "location": difference.location,
+                "legacy": difference.legacy,
+                "new": difference.new,
+            })
+        })
+        .collect();
+    let mut line = serde_json::json!({
+        "method": method.as_str(),
+        "uri": uri.to_string(),
+        "differences": differences,
+    })
+    .to_string();
+    line.push('\n');
+    line
+}
+
+/// Reads the body of the shadow target's response, up to `max_body_size`.
+async fn capture(response: Response, max_body_size: usize) -> CapturedResponse {
+    let (parts, mut body) = response.into_parts();
+    let mut captured = Vec::new();
+    while let Some(Ok(chunk)) = body.data().await {
+        let remaining = max_body_size.saturating_sub(captured.len());
+        captured.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+        if remaining <= chunk.len() {
+            break;
+        }
+    }
+    CapturedResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: captured.into(),
+    }
+}
+
+/// Hands the response of the strangled service to the comparison, once its body has been sent
+/// to the client.
+pub(crate) struct Comparison {
+    legacy: oneshot::Sender<CapturedResponse>,
+    max_body_size: usize,
+}
+
+impl Comparison {
+    pub(crate) fn capture(self, response: Response) -> Response {
+        let (parts, body) = response.into_parts();
+        let body = CaptureBody {
+            inner: body,
+            status: parts.status,
+            headers: parts.headers.clone(),
+            captured: Vec::new(),
+            comparison: Some(self),
+        };
+        Response::from_parts(parts, axum::body::boxed(body))
+    }
+}
+
+/// Passes the body through, keeping a copy for the comparison.
+struct CaptureBody {
+    inner: BoxBody,
+    status: StatusCode,
+    headers: HeaderMap,
+    captured: Vec<u8>,
+    comparison: Option<Comparison>,
+}
+
+impl CaptureBody {
+    fn finish(&mut self) {
+        if let Some(comparison) = self.comparison.take() {
+            let _ = comparison.legacy.send(CapturedResponse {
+                status: self.status,
+                headers: std::mem::take(&mut self.headers),
+                body: std::mem::take(&mut self.captured).into(),
+            });
+        }
+    }
+}
+
+impl HttpBody for CaptureBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.inner).poll_data(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(comparison) = &this.comparison {
+                    let remaining = comparison.max_body_size.saturating_sub(this.captured.len());
+                    this.captured
+                        .extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+                }
+                if this.inner.is_end_stream() {
+                    this.finish();
+                }
+            }
+            // The comparison is dropped along with the body, the response wasn't complete.
+            Poll::Ready(Some(Err(_))) => this.comparison = None,
+            Poll::Ready(None) => this.finish(),
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::routing::get;
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::Strangler;
+
+    #[tokio::test]
+    async fn mirrors_get_requests_in_the_background() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("legacy"))
+            .mount(&mock_server)
+            .await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let new_implementation = axum::Router::new().route(
+            "/users",
+            get({
+                let tx = tx.clone();
+                move || async move {
+                    tx.send("get").unwrap();
+                    "new"
+                }
+            })
+            .post(move || async move {
+                tx.send("post").unwrap();
+                "new"
+            }),
+        );
+        let strangler = Strangler::builder(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        )
+        .with_shadow(Shadow::new(new_implementation))
+        .build();
+
+        let response = strangler
+            .forward_to_strangled(Request::get("/users").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(hyper::body::to_bytes(response).await.unwrap(), "legacy");
+        let response = strangler
+            .forward_to_strangled(Request::post("/users").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(hyper::body::to_bytes(response).await.unwrap(), "legacy");
+
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), rx.recv()).await,
+            Ok(Some("get"))
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), rx.recv())
+                .await
+                .is_err(),
+            "post requests shouldn't be mirrored"
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_mismatching_responses() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/users/1"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"id": 1, "name": "legacy"}"#),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let new_implementation = axum::Router::new().route(
+            "/users/:id",
+            get(|| async { r#"{"id": 2, "name": "new"}"# }),
+        );
+        let mismatch_log = std::env::temp_dir().join(format!(
+            "axum-strangler-mismatches-{}.jsonl",
+            mock_server.address().port()
+        ));
+        let _ = std::fs::remove_file(&mismatch_log);
+        let strangler = Strangler::builder(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        )
+        .with_shadow(
+            Shadow::new(new_implementation)
+                .with_comparator(crate::JsonComparator::new().ignore_field("id"))
+                .with_mismatch_log(&mismatch_log),
+        )
+        .build();
+
+        let response = strangler
+            .forward_to_strangled(Request::get("/users/1").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(
+            hyper::body::to_bytes(response).await.unwrap(),
+            r#"{"id": 1, "name": "legacy"}"#
+        );
+
+        let mut logged = String::new();
+        for _ in 0..50 {
+            logged = std::fs::read_to_string(&mismatch_log).unwrap_or_default();
+            if !logged.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        std::fs::remove_file(&mismatch_log).unwrap();
+        let logged: serde_json::Value = serde_json::from_str(&logged).unwrap();
+        assert_eq!(
+            logged,
+            serde_json::json!({
+                "method": "GET",
+                "uri": "/users/1",
+                "differences": [
+                    { "location": "body $.name", "legacy": "\"legacy\"", "new": "\"new\"" }
+                ],
+            })
+        );
+    }
+}
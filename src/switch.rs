@@ -0,0 +1,196 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::Request,
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use tower_service::Service;
+
+use crate::{RegisteredRoute, RouteState, RoutingDecider, Shadow, Strangler};
+
+/// Sends requests to the new implementation of a route or to the strangled service, as decided
+/// per request by a [`RoutingDecider`], e.g. the current state of the route in a
+/// [`crate::RouteRegistry`] or a feature flag.
+/// In the `RouteState::Shadow` state, requests are forwarded to the strangled service and
+/// mirrored to the new implementation.
+pub struct RouteSwitch<N, L = Strangler, D = RegisteredRoute> {
+    new: N,
+    legacy: L,
+    decider: Arc<D>,
+    shadow: Shadow,
+}
+
+impl<N: Clone, L: Clone, D> Clone for RouteSwitch<N, L, D> {
+    fn clone(&self) -> Self {
+        Self {
+            new: self.new.clone(),
+            legacy: self.legacy.clone(),
+            decider: self.decider.clone(),
+            shadow: self.shadow.clone(),
+        }
+    }
+}
+
+impl<N, L, D> RouteSwitch<N, L, D> {
+    /// Mirrors requests with the defaults of [`Shadow::new`] in the `RouteState::Shadow` state.
+    pub fn new<ResBody>(new: N, legacy: L, decider: D) -> Self
+    where
+        N: Service<Request<Body>, Response = axum::http::Response<ResBody>>
+            + Clone
+            + Send
+            + 'static,
+        N::Error: Into<BoxError>,
+        N::Future: Send,
+        ResBody: HttpBody<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        Self {
+            shadow: Shadow::new(new.clone()),
+            new,
+            legacy,
+            decider: Arc::new(decider),
+        }
+    }
+
+    /// How to mirror requests in the `RouteState::Shadow` state, e.g. to compare the responses.
+    /// The shadow should target the new implementation.
+    pub fn with_shadow(self, shadow: Shadow) -> Self {
+        Self { shadow, ..self }
+    }
+}
+
+impl<N, L, D, NewResBody, LegacyResBody> Service<Request<Body>> for RouteSwitch<N, L, D>
+where
+    D: RoutingDecider + 'static,
+    N: Service<Request<Body>, Response = axum::http::Response<NewResBody>> + Clone + Send + 'static,
+    N::Future: Send,
+    L: Service<Request<Body>, Response = axum::http::Response<LegacyResBody>>
+        + Clone
+        + Send
+        + 'static,
+    L::Error: Into<N::Error>,
+    L::Future: Send,
+    NewResBody: HttpBody<Data = Bytes> + Send + 'static,
+    NewResBody::Error: Into<BoxError>,
+    LegacyResBody: HttpBody<Data = Bytes> + Send + 'static,
+    LegacyResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = N::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let decider = self.decider.clone();
+        let mut new = self.new.clone();
+        let mut legacy = self.legacy.clone();
+        let shadow = self.shadow.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let state = decider.decide(&parts).await;
+            let req = Request::from_parts(parts, body);
+            tracing::trace!(%state, uri = %req.uri(), "switching request");
+
+            if state == RouteState::New {
+                std::future::poll_fn(|cx| new.poll_ready(cx)).await?;
+                return Ok(new.call(req).await?.map(axum::body::boxed));
+            }
+
+            let shadow = (state == RouteState::Shadow).then_some(shadow);
+            let (req, comparison) = match shadow {
+                Some(shadow) => match shadow.mirror(req).await {
+                    Ok(mirrored) => mirrored,
+                    Err(e) => return Ok(e.into_response()),
+                },
+                None => (req, None),
+            };
+
+            std::future::poll_fn(|cx| legacy.poll_ready(cx))
+                .await
+                .map_err(Into::into)?;
+            let response = legacy
+                .call(req)
+                .await
+                .map_err(Into::into)?
+                .map(axum::body::boxed);
+            Ok(match comparison {
+                Some(comparison) => comparison.capture(response),
+                None => response,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::routing::get;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::RouteRegistry;
+
+    #[tokio::test]
+    async fn follows_the_route_state() {
+        let mock_server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200).set_body_string("legacy"))
+            .mount(&mock_server)
+            .await;
+        let strangler = Strangler::new(
+            axum::http::uri::Authority::try_from(format!(
+                "127.0.0.1:{}",
+                mock_server.address().port()
+            ))
+            .unwrap(),
+        );
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let new_implementation = axum::Router::new().route(
+            "/users",
+            get(move || async move {
+                tx.send(()).unwrap();
+                "new"
+            }),
+        );
+        let registry = RouteRegistry::new();
+        let switch = RouteSwitch::new(
+            new_implementation,
+            strangler,
+            registry.route("users", RouteState::New),
+        );
+
+        let body = || async {
+            let response = switch
+                .clone()
+                .call(Request::get("/users").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            hyper::body::to_bytes(response).await.unwrap()
+        };
+        assert_eq!(body().await, "new");
+        rx.recv().await.unwrap();
+
+        registry.set("users", RouteState::Legacy);
+        assert_eq!(body().await, "legacy");
+        assert!(rx.try_recv().is_err());
+
+        registry.set("users", RouteState::Shadow);
+        assert_eq!(body().await, "legacy");
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), rx.recv()).await,
+            Ok(Some(()))
+        );
+    }
+}
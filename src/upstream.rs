@@ -0,0 +1,441 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::http::{header::HeaderName, uri::Authority, HeaderMap, HeaderValue};
+
+/// How a request is assigned to one of the instances of the strangled service.
+#[derive(Clone, Debug)]
+pub enum LoadBalancing {
+    /// Every instance gets a request in turn. This is the default.
+    RoundRobin,
+    /// The instance with the fewest requests in flight gets the request.
+    LeastOutstandingRequests,
+    /// A random instance gets the request.
+    Random,
+    /// Requests with the same key always go to the same instance, as long as it's available.
+    /// Requests without the key are spread round-robin.
+    ConsistentHash(HashKey),
+}
+
+/// What to use as the key for [`LoadBalancing::ConsistentHash`] or a sticky [`crate::Canary`].
+#[derive(Clone, Debug)]
+pub enum HashKey {
+    Header(HeaderName),
+    Cookie(String),
+}
+
+/// Temporarily stops sending requests to an instance of the strangled service that keeps failing.
+/// A request counts as failed when the instance couldn't be reached, or answered with a `5xx`
+/// status code.
+/// If all instances are ejected, requests are spread over all of them anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct OutlierEjection {
+    consecutive_failures: u32,
+    ejection_time: Duration,
+}
+
+impl Default for OutlierEjection {
+    /// Ejects an instance for 30 seconds after 5 consecutive failures.
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+impl OutlierEjection {
+    pub fn new(consecutive_failures: u32, ejection_time: Duration) -> Self {
+        Self {
+            consecutive_failures,
+            ejection_time,
+        }
+    }
+}
+
+/// The state of a single instance of the strangled service, see [`crate::StranglerHealth`].
+#[derive(Clone, Debug)]
+pub struct UpstreamHealth {
+    pub(crate) authority: Authority,
+    pub(crate) healthy: bool,
+    pub(crate) ejected: bool,
+    pub(crate) outstanding_requests: usize,
+}
+
+impl UpstreamHealth {
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    /// Whether or not the instance passes its health checks, this is always `true` if there are
+    /// no health checks configured.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// Whether or not the instance was ejected by the [`OutlierEjection`].
+    pub fn is_ejected(&self) -> bool {
+        self.ejected
+    }
+
+    pub fn outstanding_requests(&self) -> usize {
+        self.outstanding_requests
+    }
+}
+
+pub(crate) struct Pool {
+    instances: Vec<Instance>,
+    load_balancing: LoadBalancing,
+    outlier_ejection: Option<OutlierEjection>,
+    next: AtomicUsize,
+}
+
+struct Instance {
+    authority: Authority,
+    /// The authority as a `host` header, for when the host header is rewritten.
+    host: HeaderValue,
+    outstanding_requests: AtomicUsize,
+    consecutive_failures: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+    healthy: AtomicBool,
+    #[cfg(feature = "health-check")]
+    consecutive_probes: AtomicU32,
+}
+
+/// An instance that was picked to handle a request, counts as an outstanding request as long as
+/// it's alive.
+pub(crate) struct Selected {
+    pool: Arc<Pool>,
+    index: usize,
+}
+
+impl Pool {
+    /// # Panics
+    /// If there are no `authorities`.
+    pub(crate) fn new(
+        authorities: Vec<Authority>,
+        load_balancing: LoadBalancing,
+        outlier_ejection: Option<OutlierEjection>,
+    ) -> Self {
+        assert!(
+            !authorities.is_empty(),
+            "the strangled service needs at least one authority"
+        );
+        Self {
+            instances: authorities
+                .into_iter()
+                .map(|authority| Instance {
+                    host: HeaderValue::from_str(authority.as_str())
+                        .expect("an authority is a valid header value"),
+                    authority,
+                    outstanding_requests: AtomicUsize::new(0),
+                    consecutive_failures: AtomicU32::new(0),
+                    ejected_until: Mutex::new(None),
+                    healthy: AtomicBool::new(true),
+                    #[cfg(feature = "health-check")]
+                    consecutive_probes: AtomicU32::new(0),
+                })
+                .collect(),
+            load_balancing,
+            outlier_ejection,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn select(self: &Arc<Self>, headers: &HeaderMap) -> Selected {
+        let now = Instant::now();
+        let mut available: Vec<(usize, &Instance)> = self
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|(_, instance)| instance.is_healthy() && instance.is_available(now))
+            .collect();
+        if available.is_empty() {
+            available = self.instances.iter().enumerate().collect();
+        }
+
+        let (index, instance) = match &self.load_balancing {
+            LoadBalancing::RoundRobin => self.round_robin(&available),
+            LoadBalancing::Random => available[fastrand::usize(..available.len())],
+            LoadBalancing::LeastOutstandingRequests => {
+                let offset = self.next.fetch_add(1, Ordering::Relaxed);
+                *available
+                    .iter()
+                    .cycle()
+                    .skip(offset % available.len())
+                    .take(available.len())
+                    .min_by_key(|(_, instance)| {
+                        instance.outstanding_requests.load(Ordering::Relaxed)
+                    })
+                    .unwrap()
+            }
+            LoadBalancing::ConsistentHash(hash_key) => match hash_key.extract(headers) {
+                // Rendezvous hashing, so only the keys of an unavailable instance move.
+                Some(key) => *available
+                    .iter()
+                    .max_by_key(|(_, instance)| {
+                        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                        key.hash(&mut hasher);
+                        instance.authority.as_str().hash(&mut hasher);
+                        hasher.finish()
+                    })
+                    .unwrap(),
+                None => self.round_robin(&available),
+            },
+        };
+
+        instance
+            .outstanding_requests
+            .fetch_add(1, Ordering::Relaxed);
+        Selected {
+            pool: self.clone(),
+            index,
+        }
+    }
+
+    fn round_robin<'a>(&self, available: &[(usize, &'a Instance)]) -> (usize, &'a Instance) {
+        available[self.next.fetch_add(1, Ordering::Relaxed) % available.len()]
+    }
+
+    pub(crate) fn health(&self) -> Vec<UpstreamHealth> {
+        let now = Instant::now();
+        self.instances
+            .iter()
+            .map(|instance| UpstreamHealth {
+                authority: instance.authority.clone(),
+                healthy: instance.is_healthy(),
+                ejected: !instance.is_available(now),
+                outstanding_requests: instance.outstanding_requests.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "health-check")]
+impl Pool {
+    pub(crate) fn authorities(&self) -> impl Iterator<Item = &Authority> {
+        self.instances.iter().map(|instance| &instance.authority)
+    }
+
+    /// Flips the health of the instance at `index` once enough probes in a row disagree with
+    /// its current health.
+    pub(crate) fn record_probe(
+        &self,
+        index: usize,
+        healthy: bool,
+        healthy_threshold: u32,
+        unhealthy_threshold: u32,
+    ) {
+        let instance = &self.instances[index];
+        if instance.is_healthy() == healthy {
+            instance.consecutive_probes.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let consecutive_probes = instance.consecutive_probes.fetch_add(1, Ordering::Relaxed) + 1;
+        let threshold = if healthy {
+            healthy_threshold
+        } else {
+            unhealthy_threshold
+        };
+        if consecutive_probes >= threshold {
+            if healthy {
+                tracing::info!(authority = %instance.authority, "instance of strangled service is healthy");
+            } else {
+                tracing::warn!(authority = %instance.authority, "instance of strangled service is unhealthy");
+            }
+            instance.healthy.store(healthy, Ordering::Relaxed);
+            instance.consecutive_probes.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl From<Authority> for Pool {
+    fn from(authority: Authority) -> Self {
+        Pool::new(
+            vec![authority],
+            LoadBalancing::RoundRobin,
+            Some(OutlierEjection::default()),
+        )
+    }
+}
+
+impl Instance {
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        match *self.ejected_until.lock().unwrap() {
+            Some(ejected_until) => ejected_until <= now,
+            None => true,
+        }
+    }
+}
+
+impl HashKey {
+    pub(crate) fn extract<'a>(&self, headers: &'a HeaderMap) -> Option<&'a [u8]> {
+        match self {
+            HashKey::Header(name) => headers.get(name).map(|value| value.as_bytes()),
+            HashKey::Cookie(name) => headers
+                .get_all(axum::http::header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(cookie_name, _)| cookie_name == name)
+                .map(|(_, value)| value.as_bytes()),
+        }
+    }
+}
+
+impl Selected {
+    fn instance(&self) -> &Instance {
+        &self.pool.instances[self.index]
+    }
+
+    pub(crate) fn authority(&self) -> &Authority {
+        &self.instance().authority
+    }
+
+    pub(crate) fn host(&self) -> &HeaderValue {
+        &self.instance().host
+    }
+
+    pub(crate) fn record(&self, success: bool) {
+        let instance = self.instance();
+        if success {
+            instance.consecutive_failures.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let consecutive_failures = instance
+            .consecutive_failures
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if let Some(outlier_ejection) = self.pool.outlier_ejection {
+            if consecutive_failures >= outlier_ejection.consecutive_failures {
+                tracing::warn!(
+                    authority = %instance.authority,
+                    consecutive_failures,
+                    "ejecting instance of strangled service"
+                );
+                instance.consecutive_failures.store(0, Ordering::Relaxed);
+                *instance.ejected_until.lock().unwrap() =
+                    Some(Instant::now() + outlier_ejection.ejection_time);
+            }
+        }
+    }
+}
+
+impl Drop for Selected {
+    fn drop(&mut self) {
+        self.instance()
+            .outstanding_requests
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(load_balancing: LoadBalancing) -> Arc<Pool> {
+        Arc::new(Pool::new(
+            vec![
+                Authority::from_static("127.0.0.1:1"),
+                Authority::from_static("127.0.0.1:2"),
+                Authority::from_static("127.0.0.1:3"),
+            ],
+            load_balancing,
+            Some(OutlierEjection::new(2, Duration::from_secs(60))),
+        ))
+    }
+
+    #[test]
+    fn round_robin() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let headers = HeaderMap::new();
+
+        let picked: Vec<_> = (0..4)
+            .map(|_| pool.select(&headers).authority().port_u16().unwrap())
+            .collect();
+
+        assert_eq!(picked, vec![1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn least_outstanding_requests() {
+        let pool = pool(LoadBalancing::LeastOutstandingRequests);
+        let headers = HeaderMap::new();
+
+        let first = pool.select(&headers);
+        let second = pool.select(&headers);
+        let third = pool.select(&headers);
+        assert_ne!(first.authority(), second.authority());
+        assert_ne!(second.authority(), third.authority());
+        assert_ne!(first.authority(), third.authority());
+
+        let freed = second.authority().clone();
+        drop(second);
+        assert_eq!(pool.select(&headers).authority(), &freed);
+    }
+
+    #[test]
+    fn consistent_hash_by_cookie() {
+        let pool = pool(LoadBalancing::ConsistentHash(HashKey::Cookie(
+            "session".to_owned(),
+        )));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::COOKIE,
+            "theme=dark; session=abc".parse().unwrap(),
+        );
+
+        let first = pool.select(&headers).authority().clone();
+        for _ in 0..10 {
+            assert_eq!(pool.select(&headers).authority(), &first);
+        }
+    }
+
+    #[test]
+    fn ejects_failing_instances() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let headers = HeaderMap::new();
+
+        for _ in 0..2 {
+            let selected = pool.select(&headers);
+            assert_eq!(selected.authority().port_u16(), Some(1));
+            selected.record(false);
+            pool.select(&headers);
+            pool.select(&headers);
+        }
+
+        for _ in 0..4 {
+            assert_ne!(pool.select(&headers).authority().port_u16(), Some(1));
+        }
+        assert!(pool.health()[0].is_ejected());
+    }
+
+    #[cfg(feature = "health-check")]
+    #[test]
+    fn skips_unhealthy_instances() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let headers = HeaderMap::new();
+
+        pool.record_probe(1, false, 1, 2);
+        assert!(pool.health()[1].is_healthy());
+        pool.record_probe(1, false, 1, 2);
+        assert!(!pool.health()[1].is_healthy());
+
+        for _ in 0..4 {
+            assert_ne!(pool.select(&headers).authority().port_u16(), Some(2));
+        }
+
+        pool.record_probe(1, true, 1, 2);
+        assert!(pool.health()[1].is_healthy());
+    }
+}